@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE livechat_super_chats (
+    super_chat_id SERIAL PRIMARY KEY,
+    youtube_id VARCHAR NOT NULL UNIQUE,
+    channel_id VARCHAR NOT NULL,
+    display_name VARCHAR NOT NULL,
+    event_type VARCHAR NOT NULL,
+    amount_micros BIGINT NOT NULL,
+    currency VARCHAR NOT NULL,
+    amount_display_string VARCHAR NOT NULL,
+    tier INTEGER NOT NULL,
+    sticker_id VARCHAR,
+    sticker_alt_text VARCHAR,
+    user_comment TEXT,
+    sent_at TIMESTAMP NOT NULL,
+    received_at TIMESTAMP NOT NULL
+)
\ No newline at end of file
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE livechat_membership_events (
+    membership_event_id SERIAL PRIMARY KEY,
+    youtube_id VARCHAR NOT NULL UNIQUE,
+    channel_id VARCHAR NOT NULL,
+    display_name VARCHAR NOT NULL,
+    event_type VARCHAR NOT NULL,
+    member_level_name VARCHAR,
+    member_month INTEGER,
+    message TEXT NOT NULL,
+    sent_at TIMESTAMP NOT NULL,
+    received_at TIMESTAMP NOT NULL
+)
\ No newline at end of file
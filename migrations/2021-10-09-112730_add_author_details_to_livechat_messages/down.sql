@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE livechat_messages
+    DROP COLUMN is_chat_owner,
+    DROP COLUMN is_chat_moderator,
+    DROP COLUMN is_chat_sponsor,
+    DROP COLUMN is_verified,
+    DROP COLUMN profile_image_url
\ No newline at end of file
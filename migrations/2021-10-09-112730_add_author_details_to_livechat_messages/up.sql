@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE livechat_messages
+    ADD COLUMN is_chat_owner BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN is_chat_moderator BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN is_chat_sponsor BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN is_verified BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN profile_image_url VARCHAR
\ No newline at end of file
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE livechat_bans;
+ALTER TABLE livechat_messages
+    DROP COLUMN deleted_at,
+    DROP COLUMN deleted_by_channel_id
\ No newline at end of file
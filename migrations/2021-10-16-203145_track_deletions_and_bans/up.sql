@@ -0,0 +1,17 @@
+-- Your SQL goes here
+ALTER TABLE livechat_messages
+    ADD COLUMN deleted_at TIMESTAMP,
+    ADD COLUMN deleted_by_channel_id VARCHAR;
+
+CREATE TABLE livechat_bans (
+    ban_id SERIAL PRIMARY KEY,
+    youtube_id VARCHAR NOT NULL UNIQUE,
+    banned_channel_id VARCHAR NOT NULL,
+    banned_display_name VARCHAR NOT NULL,
+    ban_type VARCHAR NOT NULL,
+    ban_duration_seconds BIGINT,
+    moderator_channel_id VARCHAR NOT NULL,
+    moderator_display_name VARCHAR NOT NULL,
+    banned_at TIMESTAMP NOT NULL,
+    received_at TIMESTAMP NOT NULL
+)
\ No newline at end of file
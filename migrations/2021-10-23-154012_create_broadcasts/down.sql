@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE livechat_messages DROP COLUMN broadcast_id;
+DROP TABLE broadcasts
\ No newline at end of file
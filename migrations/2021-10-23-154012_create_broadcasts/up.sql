@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE broadcasts (
+    broadcast_id SERIAL PRIMARY KEY,
+    youtube_id VARCHAR NOT NULL UNIQUE,
+    livechat_id VARCHAR NOT NULL,
+    title VARCHAR NOT NULL,
+    scheduled_start_at TIMESTAMP,
+    scheduled_end_at TIMESTAMP,
+    actual_start_at TIMESTAMP,
+    actual_end_at TIMESTAMP
+);
+
+ALTER TABLE livechat_messages
+    ADD COLUMN broadcast_id INTEGER REFERENCES broadcasts (broadcast_id)
\ No newline at end of file
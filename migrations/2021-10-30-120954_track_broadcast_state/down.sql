@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE broadcast_state_changes;
+ALTER TABLE broadcasts DROP COLUMN life_cycle_status
\ No newline at end of file
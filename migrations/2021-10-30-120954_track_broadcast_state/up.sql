@@ -0,0 +1,11 @@
+-- Your SQL goes here
+ALTER TABLE broadcasts
+    ADD COLUMN life_cycle_status VARCHAR;
+
+CREATE TABLE broadcast_state_changes (
+    state_change_id SERIAL PRIMARY KEY,
+    broadcast_id INTEGER NOT NULL REFERENCES broadcasts (broadcast_id),
+    previous_status VARCHAR NOT NULL,
+    status VARCHAR NOT NULL,
+    changed_at TIMESTAMP NOT NULL
+)
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE livechat_messages DROP COLUMN livechat_id
\ No newline at end of file
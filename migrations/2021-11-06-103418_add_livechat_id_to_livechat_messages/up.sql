@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE livechat_messages ADD COLUMN livechat_id VARCHAR
\ No newline at end of file
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE livechat_bans DROP COLUMN tenant;
+ALTER TABLE livechat_membership_events DROP COLUMN tenant;
+ALTER TABLE livechat_super_chats DROP COLUMN tenant;
+ALTER TABLE livechat_messages DROP COLUMN tenant;
+ALTER TABLE broadcasts DROP COLUMN tenant
\ No newline at end of file
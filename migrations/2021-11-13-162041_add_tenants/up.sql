@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- Everything stored before there were tenants belongs to the default tenant
+ALTER TABLE broadcasts ADD COLUMN tenant VARCHAR NOT NULL DEFAULT 'default';
+ALTER TABLE livechat_messages ADD COLUMN tenant VARCHAR NOT NULL DEFAULT 'default';
+ALTER TABLE livechat_super_chats ADD COLUMN tenant VARCHAR NOT NULL DEFAULT 'default';
+ALTER TABLE livechat_membership_events ADD COLUMN tenant VARCHAR NOT NULL DEFAULT 'default';
+ALTER TABLE livechat_bans ADD COLUMN tenant VARCHAR NOT NULL DEFAULT 'default'
\ No newline at end of file
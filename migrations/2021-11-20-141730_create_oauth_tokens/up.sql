@@ -0,0 +1,11 @@
+-- Your SQL goes here
+-- The token caches of the authenticated accounts, encrypted with YTS_TOKEN_ENCRYPTION_KEY
+CREATE TABLE oauth_tokens (
+    token_id SERIAL PRIMARY KEY,
+    tenant VARCHAR NOT NULL,
+    account VARCHAR NOT NULL,
+    nonce BYTEA NOT NULL,
+    encrypted_token BYTEA NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    UNIQUE (tenant, account)
+)
\ No newline at end of file
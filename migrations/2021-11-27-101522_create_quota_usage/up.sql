@@ -0,0 +1,12 @@
+-- Your SQL goes here
+-- The quota units used per day, the day starts at midnight Pacific Time like the YouTube quota
+CREATE TABLE quota_usage (
+    usage_id SERIAL PRIMARY KEY,
+    day DATE NOT NULL,
+    tenant VARCHAR NOT NULL,
+    account VARCHAR NOT NULL,
+    method VARCHAR NOT NULL,
+    calls INTEGER NOT NULL,
+    units INTEGER NOT NULL,
+    UNIQUE (day, tenant, account, method)
+)
\ No newline at end of file
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+-- Where the ingestion of a livechat stopped, so it can resume after a restart
+CREATE TABLE livechat_cursors (
+    cursor_id SERIAL PRIMARY KEY,
+    tenant VARCHAR NOT NULL,
+    livechat_id VARCHAR NOT NULL,
+    page_token VARCHAR,
+    last_message_id VARCHAR,
+    updated_at TIMESTAMP NOT NULL,
+    UNIQUE (tenant, livechat_id)
+)
\ No newline at end of file
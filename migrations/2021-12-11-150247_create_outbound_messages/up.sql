@@ -0,0 +1,19 @@
+-- Your SQL goes here
+-- Messages waiting to be sent, and the ones that were sent or failed, one row per livechat
+CREATE TABLE outbound_messages (
+    outbound_message_id SERIAL PRIMARY KEY,
+    tenant VARCHAR NOT NULL,
+    idempotency_key VARCHAR NOT NULL,
+    livechat_id VARCHAR NOT NULL,
+    message TEXT NOT NULL,
+    priority VARCHAR NOT NULL,
+    state VARCHAR NOT NULL,
+    attempts INTEGER NOT NULL,
+    error TEXT,
+    next_attempt_at TIMESTAMP NOT NULL,
+    queued_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    UNIQUE (tenant, idempotency_key, livechat_id)
+);
+
+CREATE INDEX outbound_messages_pending ON outbound_messages (tenant, state)
\ No newline at end of file
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE outbound_messages DROP COLUMN published_at;
+ALTER TABLE outbound_messages DROP COLUMN youtube_message_id;
+
+ALTER TABLE livechat_messages DROP COLUMN sent_by_service
\ No newline at end of file
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Messages sent through SendMessage are stored when YouTube accepts them and flagged, so they can be told apart when they come back
+ALTER TABLE livechat_messages ADD COLUMN sent_by_service BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE outbound_messages ADD COLUMN youtube_message_id VARCHAR;
+ALTER TABLE outbound_messages ADD COLUMN published_at TIMESTAMP
\ No newline at end of file
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM outbound_messages WHERE part > 0;
+ALTER TABLE outbound_messages DROP CONSTRAINT outbound_messages_tenant_idempotency_key_livechat_id_part_key;
+ALTER TABLE outbound_messages ADD UNIQUE (tenant, idempotency_key, livechat_id);
+ALTER TABLE outbound_messages DROP COLUMN part
\ No newline at end of file
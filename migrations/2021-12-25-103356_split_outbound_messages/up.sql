@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- Long messages can be split into parts, which share the idempotency key and are sent in order
+ALTER TABLE outbound_messages ADD COLUMN part INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE outbound_messages DROP CONSTRAINT outbound_messages_tenant_idempotency_key_livechat_id_key;
+ALTER TABLE outbound_messages ADD UNIQUE (tenant, idempotency_key, livechat_id, part)
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE outbound_messages DROP COLUMN sender
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- The account a message is sent as, messages queued before were all sent by the bot
+ALTER TABLE outbound_messages ADD COLUMN sender VARCHAR NOT NULL DEFAULT 'bot'
\ No newline at end of file
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+-- Announcements the bot posts to the chats of a tenant on a cron or interval schedule
+CREATE TABLE chat_timers (
+    timer_id SERIAL PRIMARY KEY,
+    tenant VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    message TEXT NOT NULL,
+    cron_schedule VARCHAR,
+    interval_seconds INTEGER,
+    min_messages INTEGER NOT NULL DEFAULT 0,
+    run_without_live_broadcast BOOLEAN NOT NULL DEFAULT FALSE,
+    paused BOOLEAN NOT NULL DEFAULT FALSE,
+    last_run_at TIMESTAMP,
+    next_run_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    UNIQUE (tenant, name)
+)
\ No newline at end of file
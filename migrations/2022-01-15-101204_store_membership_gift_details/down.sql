@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE livechat_membership_events DROP COLUMN gifting_event_id;
+ALTER TABLE livechat_membership_events DROP COLUMN gifter_channel_id;
+ALTER TABLE livechat_membership_events DROP COLUMN gift_count
\ No newline at end of file
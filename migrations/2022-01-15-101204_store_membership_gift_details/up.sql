@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- How many memberships were gifted, and who gifted the membership a user received
+ALTER TABLE livechat_membership_events ADD COLUMN gift_count INTEGER;
+ALTER TABLE livechat_membership_events ADD COLUMN gifter_channel_id VARCHAR;
+ALTER TABLE livechat_membership_events ADD COLUMN gifting_event_id VARCHAR
\ No newline at end of file
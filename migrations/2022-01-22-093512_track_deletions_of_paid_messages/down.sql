@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE livechat_membership_events DROP COLUMN deleted_by_channel_id;
+ALTER TABLE livechat_membership_events DROP COLUMN deleted_at;
+ALTER TABLE livechat_super_chats DROP COLUMN deleted_by_channel_id;
+ALTER TABLE livechat_super_chats DROP COLUMN deleted_at
\ No newline at end of file
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Super Chats, Super Stickers and membership messages can be deleted by moderators as well
+ALTER TABLE livechat_super_chats ADD COLUMN deleted_at TIMESTAMP;
+ALTER TABLE livechat_super_chats ADD COLUMN deleted_by_channel_id VARCHAR;
+ALTER TABLE livechat_membership_events ADD COLUMN deleted_at TIMESTAMP;
+ALTER TABLE livechat_membership_events ADD COLUMN deleted_by_channel_id VARCHAR
\ No newline at end of file
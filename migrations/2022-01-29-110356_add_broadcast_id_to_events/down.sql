@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE livechat_bans DROP COLUMN broadcast_id;
+ALTER TABLE livechat_membership_events DROP COLUMN broadcast_id;
+ALTER TABLE livechat_super_chats DROP COLUMN broadcast_id
\ No newline at end of file
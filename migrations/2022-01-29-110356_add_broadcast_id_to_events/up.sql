@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- Every stored message references the broadcast it was received in, not only the text messages
+ALTER TABLE livechat_super_chats
+    ADD COLUMN broadcast_id INTEGER REFERENCES broadcasts (broadcast_id);
+ALTER TABLE livechat_membership_events
+    ADD COLUMN broadcast_id INTEGER REFERENCES broadcasts (broadcast_id);
+ALTER TABLE livechat_bans
+    ADD COLUMN broadcast_id INTEGER REFERENCES broadcasts (broadcast_id)
\ No newline at end of file
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use log::info;
+use prost_types::Timestamp;
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio::sync::RwLock;
+use yup_oauth2::authenticator_delegate::{DeviceAuthResponse, DeviceFlowDelegate};
+
+use crate::youtube_service::{AuthAccount, AuthChallenge, AuthState};
+
+pub fn to_timestamp(time: DateTime<Utc>) -> Timestamp {
+    Timestamp {
+        seconds: time.timestamp() as i64,
+        nanos: time.timestamp_subsec_nanos() as i32,
+    }
+}
+
+/// One of the accounts of a tenant. Besides the bot and the streamer, a tenant can have readers,
+/// which are numbered from 1 and only used to read chats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AccountId {
+    pub account: AuthAccount,
+    pub reader: u32,
+}
+
+impl AccountId {
+    pub const BOT: AccountId = AccountId {
+        account: AuthAccount::Bot,
+        reader: 0,
+    };
+    pub const STREAMER: AccountId = AccountId {
+        account: AuthAccount::Streamer,
+        reader: 0,
+    };
+
+    pub fn reader(reader: u32) -> Self {
+        AccountId {
+            account: AuthAccount::Reader,
+            reader,
+        }
+    }
+
+    /// Parses the name the account is stored with in the database
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bot" => Some(AccountId::BOT),
+            "streamer" => Some(AccountId::STREAMER),
+            _ => name
+                .strip_prefix("reader-")
+                .and_then(|reader| reader.parse().ok())
+                .map(AccountId::reader),
+        }
+    }
+}
+
+/// The name the account is stored with in the database, e.g. "bot" or "reader-1"
+impl fmt::Display for AccountId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.account {
+            AuthAccount::Bot => write!(f, "bot"),
+            AuthAccount::Streamer => write!(f, "streamer"),
+            AuthAccount::Reader => write!(f, "reader-{}", self.reader),
+        }
+    }
+}
+
+/// The latest device flow challenge of every account, keyed by tenant and account
+#[derive(Clone)]
+pub struct AuthChallenges {
+    challenges: Arc<RwLock<HashMap<(String, AccountId), AuthChallenge>>>,
+    changes_tx: Sender<AuthChallenge>,
+}
+
+impl Default for AuthChallenges {
+    fn default() -> Self {
+        let (changes_tx, _) = tokio::sync::broadcast::channel(16);
+        AuthChallenges {
+            challenges: Arc::new(RwLock::new(HashMap::new())),
+            changes_tx,
+        }
+    }
+}
+
+impl AuthChallenges {
+    pub async fn list(&self) -> Vec<AuthChallenge> {
+        let mut challenges: Vec<AuthChallenge> =
+            self.challenges.read().await.values().cloned().collect();
+        challenges.sort_by(|a, b| {
+            (&a.tenant, a.account, a.reader).cmp(&(&b.tenant, b.account, b.reader))
+        });
+        challenges
+    }
+
+    /// Returns the latest challenge of the account
+    pub async fn get(&self, tenant: &str, account: AccountId) -> Option<AuthChallenge> {
+        self.challenges
+            .read()
+            .await
+            .get(&(tenant.to_string(), account))
+            .cloned()
+    }
+
+    pub fn subscribe(&self) -> Receiver<AuthChallenge> {
+        self.changes_tx.subscribe()
+    }
+
+    /// Stores the challenge as the latest one of its account and notifies everyone who subscribed
+    async fn update(&self, account: AccountId, challenge: AuthChallenge) {
+        self.challenges
+            .write()
+            .await
+            .insert((challenge.tenant.clone(), account), challenge.clone());
+        // Nobody listening is not an error
+        let _ = self.changes_tx.send(challenge);
+    }
+
+    /// Records that the operator has to enter the user code to authenticate the account
+    pub async fn present(
+        &self,
+        tenant: &str,
+        account: AccountId,
+        device_auth_response: &DeviceAuthResponse,
+    ) {
+        self.update(
+            account,
+            AuthChallenge {
+                tenant: tenant.to_string(),
+                account: account.account as i32,
+                reader: account.reader,
+                state: AuthState::AuthPending as i32,
+                verification_url: device_auth_response.verification_uri.clone(),
+                user_code: device_auth_response.user_code.clone(),
+                expires_at_timestamp: Some(to_timestamp(device_auth_response.expires_at)),
+                updated_at_timestamp: Some(to_timestamp(Utc::now())),
+            },
+        )
+        .await;
+    }
+
+    /// Records how the authentication of the account ended, the code of the challenge is not needed anymore
+    pub async fn finish(&self, tenant: &str, account: AccountId, state: AuthState) {
+        self.update(
+            account,
+            AuthChallenge {
+                tenant: tenant.to_string(),
+                account: account.account as i32,
+                reader: account.reader,
+                state: state as i32,
+                updated_at_timestamp: Some(to_timestamp(Utc::now())),
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+}
+
+/// Hands the device flow challenges of an account to `AuthChallenges` instead of only printing them,
+/// so the accounts can be authenticated through the gRPC API as well
+pub struct ChallengeDelegate {
+    tenant: String,
+    account: AccountId,
+    challenges: AuthChallenges,
+}
+
+impl ChallengeDelegate {
+    pub fn new(tenant: &str, account: AccountId, challenges: AuthChallenges) -> Self {
+        ChallengeDelegate {
+            tenant: tenant.to_string(),
+            account,
+            challenges,
+        }
+    }
+}
+
+impl DeviceFlowDelegate for ChallengeDelegate {
+    fn present_user_code<'a>(
+        &'a self,
+        device_auth_response: &'a DeviceAuthResponse,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(async move {
+            // The log still works for anyone who can read it
+            info!(
+                "Please enter {} at {} to authenticate the {} account of tenant {}, the code expires at {}",
+                device_auth_response.user_code,
+                device_auth_response.verification_uri,
+                self.account,
+                self.tenant,
+                device_auth_response.expires_at
+            );
+            self.challenges
+                .present(&self.tenant, self.account, device_auth_response)
+                .await;
+        })
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use google_youtube3::api::LiveBroadcast;
+use log::{debug, error, info};
+use prost_types::Timestamp;
+use r2d2::Pool;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::RwLock;
+
+use crate::models::{InsertBroadcast, InsertBroadcastStateChange};
+use crate::tenant::Tenant;
+use crate::youtube::{get_broadcasts_by_id, list_broadcasts};
+use crate::youtube_service::{
+    chat_event, BroadcastSelectionPolicy, BroadcastStateChange, BroadcastStatus, ChatEvent,
+};
+use crate::{schema, upsert_broadcast};
+
+/// The last known state of every broadcast the watcher has seen, keyed by the YouTube ID of the broadcast
+pub type BroadcastStates = Arc<RwLock<HashMap<String, BroadcastStateChange>>>;
+
+/// Maps the life cycle status YouTube reports for a broadcast to a broadcast status
+pub fn broadcast_status_of(broadcast: &LiveBroadcast) -> BroadcastStatus {
+    let life_cycle_status = broadcast
+        .status
+        .as_ref()
+        .and_then(|status| status.life_cycle_status.as_deref());
+    match life_cycle_status {
+        Some("created") | Some("ready") | Some("testStarting") | Some("testing") => {
+            BroadcastStatus::Upcoming
+        }
+        Some("liveStarting") | Some("live") => BroadcastStatus::Live,
+        Some("complete") | Some("revoked") => BroadcastStatus::Complete,
+        _ => BroadcastStatus::UnknownBroadcastStatus,
+    }
+}
+
+/// Returns the name a broadcast status is stored with in the database
+pub fn broadcast_status_name(status: BroadcastStatus) -> &'static str {
+    match status {
+        BroadcastStatus::UnknownBroadcastStatus => "unknown",
+        BroadcastStatus::Upcoming => "upcoming",
+        BroadcastStatus::Live => "live",
+        BroadcastStatus::Complete => "complete",
+    }
+}
+
+/// Parses a broadcast status stored in the database
+pub fn broadcast_status_from_name(name: &str) -> BroadcastStatus {
+    match name {
+        "upcoming" => BroadcastStatus::Upcoming,
+        "live" => BroadcastStatus::Live,
+        "complete" => BroadcastStatus::Complete,
+        _ => BroadcastStatus::UnknownBroadcastStatus,
+    }
+}
+
+/// Reads the last status of a broadcast from the database, so a restart does not report every broadcast again
+fn load_stored_status(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    broadcast_youtube_id: &str,
+) -> Result<BroadcastStatus, Box<dyn std::error::Error>> {
+    use schema::broadcasts::dsl::{broadcasts, life_cycle_status, youtube_id};
+    let stored_status: Option<Option<String>> = broadcasts
+        .filter(youtube_id.eq(broadcast_youtube_id))
+        .select(life_cycle_status)
+        .first(&database_connection.get()?)
+        .optional()?;
+    Ok(stored_status
+        .flatten()
+        .map(|name| broadcast_status_from_name(name.as_str()))
+        .unwrap_or(BroadcastStatus::UnknownBroadcastStatus))
+}
+
+/// Builds a broadcast from the last known state, e.g. when a broadcast has to be updated without the YouTube API
+fn insert_broadcast_from_state(state: &BroadcastStateChange) -> InsertBroadcast {
+    InsertBroadcast {
+        youtube_id: state.broadcast_id.clone(),
+        livechat_id: state.livechat_id.clone(),
+        title: state.title.clone(),
+        scheduled_start_at: None,
+        scheduled_end_at: None,
+        actual_start_at: None,
+        actual_end_at: None,
+        life_cycle_status: None,
+    }
+}
+
+/// Stores the new status of the broadcast if it changed, records the transition and notifies subscribers
+pub async fn update_broadcast_state(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant: &Tenant,
+    mut insert_broadcast: InsertBroadcast,
+    status: BroadcastStatus,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let states = &tenant.broadcast_states;
+    if status == BroadcastStatus::UnknownBroadcastStatus {
+        return Ok(());
+    }
+
+    // Find out which status the broadcast had before
+    let known_status = states
+        .read()
+        .await
+        .get(&insert_broadcast.youtube_id)
+        .and_then(|state| BroadcastStatus::from_i32(state.status));
+    let previous_status = match known_status {
+        Some(known_status) => known_status,
+        None => load_stored_status(database_connection, &insert_broadcast.youtube_id)?,
+    };
+    // A completed broadcast never comes back, YouTube just sometimes still lists it as active for a while
+    if previous_status == status || previous_status == BroadcastStatus::Complete {
+        // Broadcasts only known from the database still have to be remembered to notice when they end
+        if known_status.is_none() {
+            let state = BroadcastStateChange {
+                broadcast_id: insert_broadcast.youtube_id.clone(),
+                livechat_id: insert_broadcast.livechat_id.clone(),
+                title: insert_broadcast.title.clone(),
+                previous_status: previous_status as i32,
+                status: previous_status as i32,
+                changed_at_timestamp: None,
+            };
+            states
+                .write()
+                .await
+                .insert(insert_broadcast.youtube_id, state);
+        }
+        return Ok(());
+    }
+
+    // Store the broadcast with its new status and record the transition
+    insert_broadcast.life_cycle_status = Some(broadcast_status_name(status).to_string());
+    let broadcast_row_id = upsert_broadcast(database_connection, &tenant.name, &insert_broadcast)?;
+    let changed_at = chrono::Utc::now();
+    let insert_state_change = InsertBroadcastStateChange {
+        broadcast_id: broadcast_row_id,
+        previous_status: broadcast_status_name(previous_status).to_string(),
+        status: broadcast_status_name(status).to_string(),
+        changed_at: changed_at.naive_utc(),
+    };
+    diesel::insert_into(schema::broadcast_state_changes::table)
+        .values(insert_state_change)
+        .execute(&database_connection.get()?)?;
+
+    info!(
+        "Broadcast {} ({}) changed from {:?} to {:?}",
+        insert_broadcast.title, insert_broadcast.youtube_id, previous_status, status
+    );
+    let state_change = BroadcastStateChange {
+        broadcast_id: insert_broadcast.youtube_id.clone(),
+        livechat_id: insert_broadcast.livechat_id.clone(),
+        title: insert_broadcast.title.clone(),
+        previous_status: previous_status as i32,
+        status: status as i32,
+        changed_at_timestamp: Some(Timestamp {
+            seconds: changed_at.timestamp() as i64,
+            nanos: changed_at.timestamp_subsec_nanos() as i32,
+        }),
+    };
+    states
+        .write()
+        .await
+        .insert(insert_broadcast.youtube_id, state_change.clone());
+    // Nobody listening is not an error
+    let _ = tenant.broadcast_state_tx.send(state_change);
+    Ok(())
+}
+
+/// Asks YouTube for the status of the active broadcasts, and the upcoming ones if asked to, as well as the ones that disappeared since the last check.
+/// Returns false if the broadcasts couldn't be listed.
+async fn check_broadcasts(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant: &Tenant,
+    include_upcoming: bool,
+) -> bool {
+    let broadcast_statuses: &[&str] = if include_upcoming {
+        &["active", "upcoming"]
+    } else {
+        &["active"]
+    };
+    let mut seen_broadcast_ids: HashSet<String> = HashSet::new();
+    for broadcast_status in broadcast_statuses {
+        let broadcasts = match list_broadcasts(tenant, broadcast_status, "all").await {
+            Some(broadcasts) => broadcasts,
+            // If we can't see everything, we can't tell which broadcasts disappeared either
+            None => return false,
+        };
+        for broadcast in broadcasts {
+            seen_broadcast_ids.insert(broadcast.id.clone().unwrap_or_default());
+            let status = broadcast_status_of(&broadcast);
+            let update_result = update_broadcast_state(
+                database_connection,
+                tenant,
+                InsertBroadcast::from(&broadcast),
+                status,
+            )
+            .await;
+            if let Err(e) = update_result {
+                error!("Error while updating broadcast state: {}", e);
+            }
+        }
+    }
+
+    // Broadcasts that were upcoming or live before but aren't listed anymore have most likely ended.
+    // Upcoming broadcasts can only be missed if the upcoming ones were listed.
+    let vanished_states: Vec<BroadcastStateChange> = tenant
+        .broadcast_states
+        .read()
+        .await
+        .values()
+        .filter(|state| {
+            !seen_broadcast_ids.contains(&state.broadcast_id)
+                && state.status != BroadcastStatus::Complete as i32
+                && (include_upcoming || state.status != BroadcastStatus::Upcoming as i32)
+        })
+        .cloned()
+        .collect();
+    if vanished_states.is_empty() {
+        return true;
+    }
+    let vanished_ids: Vec<String> = vanished_states
+        .iter()
+        .map(|state| state.broadcast_id.clone())
+        .collect();
+    let broadcasts = match get_broadcasts_by_id(tenant, &vanished_ids).await {
+        Some(broadcasts) => broadcasts,
+        None => return true,
+    };
+    for state in vanished_states {
+        let broadcast = broadcasts
+            .iter()
+            .find(|broadcast| broadcast.id.as_ref() == Some(&state.broadcast_id));
+        // Deleted broadcasts aren't returned at all, so they are treated as complete
+        let (insert_broadcast, status) = match broadcast {
+            Some(broadcast) => (
+                InsertBroadcast::from(broadcast),
+                broadcast_status_of(broadcast),
+            ),
+            None => (
+                insert_broadcast_from_state(&state),
+                BroadcastStatus::Complete,
+            ),
+        };
+        let update_result =
+            update_broadcast_state(database_connection, tenant, insert_broadcast, status).await;
+        if let Err(e) = update_result {
+            error!("Error while updating broadcast state: {}", e);
+        }
+    }
+    true
+}
+
+/// Reads an interval in seconds from the environment
+fn interval_from_env(name: &str, default_secs: u64) -> Duration {
+    let secs = env::var(name)
+        .ok()
+        .and_then(|interval| interval.parse().ok())
+        .unwrap_or(default_secs);
+    Duration::from_secs(secs)
+}
+
+/// Marks the broadcast the livechat belongs to as complete, because YouTube reported that the chat has ended
+async fn end_broadcast_of_livechat(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant: &Tenant,
+    livechat_id: &str,
+) {
+    let state = tenant
+        .broadcast_states
+        .read()
+        .await
+        .values()
+        .find(|state| state.livechat_id == livechat_id)
+        .cloned();
+    let state = match state {
+        Some(state) => state,
+        None => {
+            debug!("Chat {} ended, but its broadcast is unknown", livechat_id);
+            return;
+        }
+    };
+    let update_result = update_broadcast_state(
+        database_connection,
+        tenant,
+        insert_broadcast_from_state(&state),
+        BroadcastStatus::Complete,
+    )
+    .await;
+    if let Err(e) = update_result {
+        error!("Error while updating broadcast state: {}", e);
+    }
+}
+
+/// Periodically checks the broadcasts of the streamer of the tenant and reports every status change.
+/// A chat ended event from the ingestion loop completes the broadcast right away.
+/// Every check costs quota, so active broadcasts are checked every YTS_BROADCAST_WATCH_INTERVAL seconds, 30 by default,
+/// upcoming ones only every YTS_BROADCAST_UPCOMING_INTERVAL seconds, 600 by default,
+/// and while a chat is followed, its end is reported by the chat itself, so the checks slow down to every YTS_BROADCAST_FOLLOWING_INTERVAL seconds, 300 by default.
+/// Only when every active broadcast is followed, the checks keep their pace, as new broadcasts are found through them.
+pub async fn watch_broadcasts(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant: &Tenant,
+) {
+    let interval = interval_from_env("YTS_BROADCAST_WATCH_INTERVAL", 30);
+    let upcoming_interval = interval_from_env("YTS_BROADCAST_UPCOMING_INTERVAL", 600);
+    let following_interval = interval_from_env("YTS_BROADCAST_FOLLOWING_INTERVAL", 300);
+    let mut upcoming_checked_at: Option<Instant> = None;
+    let mut events_rx = tenant.events_tx.subscribe();
+    loop {
+        let include_upcoming = match upcoming_checked_at {
+            Some(checked_at) => checked_at.elapsed() >= upcoming_interval,
+            None => true,
+        };
+        let checked = check_broadcasts(database_connection, tenant, include_upcoming).await;
+        if checked && include_upcoming {
+            upcoming_checked_at = Some(Instant::now());
+        }
+
+        // Wait for the next check, but react to ended chats in the meantime
+        let following_all = tenant.broadcast_selection.get().await.policy
+            == BroadcastSelectionPolicy::AllActive as i32;
+        let following = !following_all && !tenant.livechats.read().await.is_empty();
+        let wait_for = if following {
+            following_interval.max(interval)
+        } else {
+            interval
+        };
+        let sleep = tokio::time::sleep(wait_for);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => break,
+                event = events_rx.recv() => match event {
+                    Ok(ChatEvent {
+                        livechat_id,
+                        event: Some(chat_event::Event::ChatEnded(_)),
+                        ..
+                    }) => {
+                        end_broadcast_of_livechat(database_connection, tenant, &livechat_id).await;
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => {
+                        (&mut sleep).await;
+                        break;
+                    }
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use r2d2::Pool;
+
+use crate::models::{InsertLivechatCursor, LivechatCursor};
+use crate::schema;
+
+/// Reads where the ingestion of the livechat stopped, if it was followed before
+pub fn load_cursor(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant_name: &str,
+    chat_id: &str,
+) -> Result<Option<LivechatCursor>, Box<dyn std::error::Error>> {
+    use schema::livechat_cursors::dsl::*;
+    let cursor = livechat_cursors
+        .filter(tenant.eq(tenant_name))
+        .filter(livechat_id.eq(chat_id))
+        .first::<LivechatCursor>(&database_connection.get()?)
+        .optional()?;
+    Ok(cursor)
+}
+
+/// Stores the page token of the next request and the id of the last processed message of the livechat
+pub fn store_cursor(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant_name: &str,
+    chat_id: &str,
+    next_page_token: Option<&str>,
+    last_seen_message_id: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use schema::livechat_cursors::dsl::*;
+    let insert_cursor = InsertLivechatCursor {
+        tenant: tenant_name.to_string(),
+        livechat_id: chat_id.to_string(),
+        page_token: next_page_token.map(|token| token.to_string()),
+        last_message_id: last_seen_message_id.map(|message_id| message_id.to_string()),
+        updated_at: chrono::Utc::now().naive_utc(),
+    };
+    diesel::insert_into(livechat_cursors)
+        .values(&insert_cursor)
+        .on_conflict((tenant, livechat_id))
+        .do_update()
+        .set((
+            page_token.eq(&insert_cursor.page_token),
+            last_message_id.eq(&insert_cursor.last_message_id),
+            updated_at.eq(insert_cursor.updated_at),
+        ))
+        .execute(&database_connection.get()?)?;
+    Ok(())
+}
+
+/// Forgets the cursor of a livechat that ended, nothing will be posted to it anymore
+pub fn delete_cursor(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant_name: &str,
+    chat_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use schema::livechat_cursors::dsl::*;
+    diesel::delete(
+        livechat_cursors
+            .filter(tenant.eq(tenant_name))
+            .filter(livechat_id.eq(chat_id)),
+    )
+    .execute(&database_connection.get()?)?;
+    Ok(())
+}
@@ -2,6 +2,7 @@ use fern::{
     colors::{Color, ColoredLevelConfig},
 };
 use log::error;
+use tonic::{Code, Status};
 
 use crate::youtube::body_to_string;
 
@@ -101,3 +102,22 @@ pub async fn log_google_errors(error: google_youtube3::Error) -> String {
         }
     }
 }
+
+/// Handles YouTube errors and converts them into a gRPC status that can be returned to the client
+pub async fn google_error_to_status(error: google_youtube3::Error) -> Status {
+    let code = match &error {
+        google_youtube3::Error::BadRequest(bad_request) => match bad_request.error.code {
+            400 => Code::InvalidArgument,
+            401 => Code::Unauthenticated,
+            403 => Code::PermissionDenied,
+            404 => Code::NotFound,
+            429 => Code::ResourceExhausted,
+            _ => Code::Internal,
+        },
+        google_youtube3::Error::MissingToken(_) => Code::Unauthenticated,
+        google_youtube3::Error::Cancelled => Code::Cancelled,
+        _ => Code::Internal,
+    };
+    let message = log_google_errors(error).await;
+    Status::new(code, message)
+}
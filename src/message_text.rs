@@ -0,0 +1,107 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// YouTube rejects chat messages that are longer than this
+pub const MAX_MESSAGE_LENGTH: usize = 200;
+
+/// Returns the length of the text the way YouTube counts it, in user-perceived characters,
+/// so an emoji made of several code points counts once
+pub fn message_length(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+/// Splits the text on word boundaries into parts that are short enough to be sent.
+/// Words that are too long on their own are cut, whitespace between words becomes a single space.
+pub fn split_message(text: &str) -> Vec<String> {
+    let words = text.split_whitespace().flat_map(|word| {
+        let graphemes: Vec<&str> = word.graphemes(true).collect();
+        graphemes
+            .chunks(MAX_MESSAGE_LENGTH)
+            .map(|chunk| chunk.concat())
+            .collect::<Vec<String>>()
+    });
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut part_length = 0;
+    for word in words {
+        let word_length = message_length(&word);
+        if part_length > 0 && part_length + 1 + word_length > MAX_MESSAGE_LENGTH {
+            parts.push(std::mem::take(&mut part));
+            part_length = 0;
+        }
+        if part_length > 0 {
+            part.push(' ');
+            part_length += 1;
+        }
+        part.push_str(&word);
+        part_length += word_length;
+    }
+    if !part.is_empty() {
+        parts.push(part);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emoji_made_of_several_code_points_count_once() {
+        assert_eq!(message_length("👍🏽"), 1);
+        assert_eq!(message_length("👨‍👩‍👧"), 1);
+        assert_eq!(message_length("e\u{301}"), 1);
+        assert_eq!(message_length("hi 👨‍👩‍👧"), 4);
+    }
+
+    #[test]
+    fn emoji_are_not_cut_apart() {
+        let family = "👨‍👩‍👧";
+        let text = family.repeat(MAX_MESSAGE_LENGTH + 1);
+        let parts = split_message(&text);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(message_length(&parts[0]), MAX_MESSAGE_LENGTH);
+        assert_eq!(parts[1], family);
+        assert_eq!(parts.concat(), text);
+    }
+
+    #[test]
+    fn words_longer_than_a_message_are_cut() {
+        let parts = split_message(&"a".repeat(450));
+        let lengths: Vec<usize> = parts.iter().map(|part| message_length(part)).collect();
+        assert_eq!(lengths, vec![200, 200, 50]);
+
+        let parts = split_message(&format!("hi {}", "a".repeat(250)));
+        assert_eq!(
+            parts,
+            vec!["hi".to_string(), "a".repeat(200), "a".repeat(50)]
+        );
+    }
+
+    #[test]
+    fn whitespace_only_has_no_parts() {
+        assert_eq!(split_message(""), Vec::<String>::new());
+        assert_eq!(split_message("  \n\t "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn whitespace_between_words_becomes_a_single_space() {
+        assert_eq!(split_message("  hello \n\t world  "), vec!["hello world"]);
+    }
+
+    #[test]
+    fn messages_of_exactly_the_maximum_length_stay_whole() {
+        let text = "a".repeat(MAX_MESSAGE_LENGTH);
+        assert_eq!(split_message(&text), vec![text]);
+
+        let text = format!("{} {}", "a".repeat(99), "b".repeat(100));
+        assert_eq!(message_length(&text), MAX_MESSAGE_LENGTH);
+        assert_eq!(split_message(&text), vec![text]);
+    }
+
+    #[test]
+    fn messages_one_over_the_maximum_length_are_split() {
+        let text = format!("{} {}", "a".repeat(100), "b".repeat(100));
+        assert_eq!(message_length(&text), MAX_MESSAGE_LENGTH + 1);
+        assert_eq!(split_message(&text), vec!["a".repeat(100), "b".repeat(100)]);
+    }
+}
@@ -0,0 +1,102 @@
+use std::convert::Infallible;
+use std::env;
+use std::fmt::Write;
+use std::net::SocketAddr;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use log::{error, info};
+
+use crate::auth::AccountId;
+use crate::quota::QuotaTracker;
+use crate::youtube_service::{AuthAccount, QuotaUsageEntry};
+
+/// Returns the account the usage is from
+fn usage_account(usage: &QuotaUsageEntry) -> AccountId {
+    AccountId {
+        account: AuthAccount::from_i32(usage.account).unwrap_or(AuthAccount::Bot),
+        reader: usage.reader,
+    }
+}
+
+/// Renders the quota usage in the Prometheus text format
+async fn render_metrics(quota: &QuotaTracker) -> String {
+    let status = match quota.status().await {
+        Ok(status) => status,
+        Err(e) => {
+            error!("Unable to read the quota usage: {}", e);
+            return String::new();
+        }
+    };
+    let mut metrics = String::new();
+    let _ = writeln!(
+        metrics,
+        "# HELP youtube_quota_units_used Quota units used on the current quota day"
+    );
+    let _ = writeln!(metrics, "# TYPE youtube_quota_units_used gauge");
+    let _ = writeln!(metrics, "youtube_quota_units_used {}", status.units_used);
+    let _ = writeln!(metrics, "# HELP youtube_quota_budget Daily quota budget");
+    let _ = writeln!(metrics, "# TYPE youtube_quota_budget gauge");
+    let _ = writeln!(metrics, "youtube_quota_budget {}", status.budget);
+    let _ = writeln!(
+        metrics,
+        "# HELP youtube_quota_threshold Quota units above which quota is saved"
+    );
+    let _ = writeln!(metrics, "# TYPE youtube_quota_threshold gauge");
+    let _ = writeln!(metrics, "youtube_quota_threshold {}", status.threshold);
+    let _ = writeln!(
+        metrics,
+        "# HELP youtube_api_calls YouTube API calls on the current quota day"
+    );
+    let _ = writeln!(metrics, "# TYPE youtube_api_calls gauge");
+    for usage in &status.usage {
+        let _ = writeln!(
+            metrics,
+            "youtube_api_calls{{tenant=\"{}\",account=\"{}\",method=\"{}\"}} {}",
+            usage.tenant,
+            usage_account(usage),
+            usage.method,
+            usage.calls
+        );
+    }
+    let _ = writeln!(
+        metrics,
+        "# HELP youtube_api_units Quota units used by YouTube API calls on the current quota day"
+    );
+    let _ = writeln!(metrics, "# TYPE youtube_api_units gauge");
+    for usage in &status.usage {
+        let _ = writeln!(
+            metrics,
+            "youtube_api_units{{tenant=\"{}\",account=\"{}\",method=\"{}\"}} {}",
+            usage.tenant,
+            usage_account(usage),
+            usage.method,
+            usage.units
+        );
+    }
+    metrics
+}
+
+/// Serves the metrics over HTTP for Prometheus if YTS_METRICS_ADDRESS is set, e.g. to 0.0.0.0:9090
+pub async fn serve_metrics(quota: QuotaTracker) {
+    let addr: SocketAddr = match env::var("YTS_METRICS_ADDRESS") {
+        Ok(addr) if !addr.is_empty() => addr.parse().expect("YTS_METRICS_ADDRESS"),
+        _ => return,
+    };
+    let make_service = make_service_fn(move |_| {
+        let quota = quota.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |_: Request<Body>| {
+                let quota = quota.clone();
+                async move {
+                    let metrics = render_metrics(&quota).await;
+                    Ok::<_, Infallible>(Response::new(Body::from(metrics)))
+                }
+            }))
+        }
+    });
+    info!("Serving metrics on {}", addr);
+    if let Err(e) = Server::bind(&addr).serve(make_service).await {
+        error!("Error while serving metrics: {}", e);
+    }
+}
@@ -1,9 +1,15 @@
+use crate::youtube_service::YouTubeChatMessageType;
 use crate::YouTubeChatMessage;
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
 use diesel::Queryable;
+use google_youtube3::api::LiveBroadcast;
 use std::convert::TryInto;
 
-use super::schema::livechat_messages;
+use super::schema::{
+    broadcast_state_changes, broadcasts, chat_timers, livechat_bans, livechat_cursors,
+    livechat_membership_events, livechat_messages, livechat_super_chats, oauth_tokens,
+    outbound_messages, quota_usage,
+};
 
 #[derive(Queryable)]
 pub struct LivechatMessage {
@@ -14,6 +20,17 @@ pub struct LivechatMessage {
     pub message: String,
     pub sent_at: NaiveDateTime,
     pub received_at: NaiveDateTime,
+    pub is_chat_owner: bool,
+    pub is_chat_moderator: bool,
+    pub is_chat_sponsor: bool,
+    pub is_verified: bool,
+    pub profile_image_url: Option<String>,
+    pub deleted_at: Option<NaiveDateTime>,
+    pub deleted_by_channel_id: Option<String>,
+    pub broadcast_id: Option<i32>,
+    pub livechat_id: Option<String>,
+    pub tenant: String,
+    pub sent_by_service: bool,
 }
 
 #[derive(Insertable)]
@@ -25,6 +42,14 @@ pub struct InsertLivechatMessage {
     pub message: String,
     pub sent_at: NaiveDateTime,
     pub received_at: NaiveDateTime,
+    pub is_chat_owner: bool,
+    pub is_chat_moderator: bool,
+    pub is_chat_sponsor: bool,
+    pub is_verified: bool,
+    pub profile_image_url: Option<String>,
+    pub broadcast_id: Option<i32>,
+    pub livechat_id: Option<String>,
+    pub sent_by_service: bool,
 }
 
 impl From<YouTubeChatMessage> for InsertLivechatMessage {
@@ -44,6 +69,14 @@ impl From<YouTubeChatMessage> for InsertLivechatMessage {
             sent_at: sent_at_chrono,
             received_at: received_at_chrono,
             youtube_id: msg.message_id,
+            is_chat_owner: msg.is_chat_owner,
+            is_chat_moderator: msg.is_chat_moderator,
+            is_chat_sponsor: msg.is_chat_sponsor,
+            is_verified: msg.is_verified,
+            profile_image_url: Some(msg.profile_image_url).filter(|s| !s.is_empty()),
+            broadcast_id: None,
+            livechat_id: Some(msg.livechat_id).filter(|s| !s.is_empty()),
+            sent_by_service: msg.sent_by_service,
         }
     }
 }
@@ -65,6 +98,380 @@ impl From<&YouTubeChatMessage> for InsertLivechatMessage {
             sent_at: sent_at_chrono,
             received_at: received_at_chrono,
             youtube_id: msg.message_id.clone(),
+            is_chat_owner: msg.is_chat_owner,
+            is_chat_moderator: msg.is_chat_moderator,
+            is_chat_sponsor: msg.is_chat_sponsor,
+            is_verified: msg.is_verified,
+            profile_image_url: Some(msg.profile_image_url.clone()).filter(|s| !s.is_empty()),
+            broadcast_id: None,
+            livechat_id: Some(msg.livechat_id.clone()).filter(|s| !s.is_empty()),
+            sent_by_service: msg.sent_by_service,
         }
     }
 }
+
+#[derive(Queryable)]
+pub struct LivechatSuperChat {
+    pub super_chat_id: i32,
+    pub youtube_id: String,
+    pub channel_id: String,
+    pub display_name: String,
+    pub event_type: String,
+    pub amount_micros: i64,
+    pub currency: String,
+    pub amount_display_string: String,
+    pub tier: i32,
+    pub sticker_id: Option<String>,
+    pub sticker_alt_text: Option<String>,
+    pub user_comment: Option<String>,
+    pub sent_at: NaiveDateTime,
+    pub received_at: NaiveDateTime,
+    pub tenant: String,
+    pub deleted_at: Option<NaiveDateTime>,
+    pub deleted_by_channel_id: Option<String>,
+    pub broadcast_id: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name = "livechat_super_chats"]
+pub struct InsertLivechatSuperChat {
+    pub youtube_id: String,
+    pub channel_id: String,
+    pub display_name: String,
+    pub event_type: String,
+    pub amount_micros: i64,
+    pub currency: String,
+    pub amount_display_string: String,
+    pub tier: i32,
+    pub sticker_id: Option<String>,
+    pub sticker_alt_text: Option<String>,
+    pub user_comment: Option<String>,
+    pub sent_at: NaiveDateTime,
+    pub received_at: NaiveDateTime,
+}
+
+impl From<&YouTubeChatMessage> for InsertLivechatSuperChat {
+    fn from(msg: &YouTubeChatMessage) -> Self {
+        let sent_at_ts = msg.sent_at_timestamp.as_ref().unwrap();
+        let sent_at_chrono =
+            NaiveDateTime::from_timestamp(sent_at_ts.seconds, sent_at_ts.nanos.try_into().unwrap());
+        let received_at_ts = msg.received_at_timestamp.as_ref().unwrap();
+        let received_at_chrono = NaiveDateTime::from_timestamp(
+            received_at_ts.seconds,
+            received_at_ts.nanos.try_into().unwrap(),
+        );
+        let details = msg.super_chat_details.as_ref().unwrap();
+        let event_type = if msg.message_type == YouTubeChatMessageType::SuperSticker as i32 {
+            "superStickerEvent"
+        } else {
+            "superChatEvent"
+        };
+        InsertLivechatSuperChat {
+            youtube_id: msg.message_id.clone(),
+            channel_id: msg.channel_id.clone(),
+            display_name: msg.display_name.clone(),
+            event_type: event_type.to_string(),
+            amount_micros: details.amount_micros as i64,
+            currency: details.currency.clone(),
+            amount_display_string: details.amount_display_string.clone(),
+            tier: details.tier as i32,
+            sticker_id: Some(details.sticker_id.clone()).filter(|s| !s.is_empty()),
+            sticker_alt_text: Some(details.sticker_alt_text.clone()).filter(|s| !s.is_empty()),
+            user_comment: Some(msg.message.clone()).filter(|s| !s.is_empty()),
+            sent_at: sent_at_chrono,
+            received_at: received_at_chrono,
+        }
+    }
+}
+
+#[derive(Queryable)]
+pub struct LivechatMembershipEvent {
+    pub membership_event_id: i32,
+    pub youtube_id: String,
+    pub channel_id: String,
+    pub display_name: String,
+    pub event_type: String,
+    pub member_level_name: Option<String>,
+    pub member_month: Option<i32>,
+    pub message: String,
+    pub sent_at: NaiveDateTime,
+    pub received_at: NaiveDateTime,
+    pub tenant: String,
+    pub gift_count: Option<i32>,
+    pub gifter_channel_id: Option<String>,
+    pub gifting_event_id: Option<String>,
+    pub deleted_at: Option<NaiveDateTime>,
+    pub deleted_by_channel_id: Option<String>,
+    pub broadcast_id: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name = "livechat_membership_events"]
+pub struct InsertLivechatMembershipEvent {
+    pub youtube_id: String,
+    pub channel_id: String,
+    pub display_name: String,
+    pub event_type: String,
+    pub member_level_name: Option<String>,
+    pub member_month: Option<i32>,
+    pub message: String,
+    pub sent_at: NaiveDateTime,
+    pub received_at: NaiveDateTime,
+    pub gift_count: Option<i32>,
+    pub gifter_channel_id: Option<String>,
+    pub gifting_event_id: Option<String>,
+}
+
+impl From<&YouTubeChatMessage> for InsertLivechatMembershipEvent {
+    fn from(msg: &YouTubeChatMessage) -> Self {
+        let sent_at_ts = msg.sent_at_timestamp.as_ref().unwrap();
+        let sent_at_chrono =
+            NaiveDateTime::from_timestamp(sent_at_ts.seconds, sent_at_ts.nanos.try_into().unwrap());
+        let received_at_ts = msg.received_at_timestamp.as_ref().unwrap();
+        let received_at_chrono = NaiveDateTime::from_timestamp(
+            received_at_ts.seconds,
+            received_at_ts.nanos.try_into().unwrap(),
+        );
+        let details = msg.membership_details.clone().unwrap_or_default();
+        let event_type = match YouTubeChatMessageType::from_i32(msg.message_type) {
+            Some(YouTubeChatMessageType::MemberMilestone) => "memberMilestoneChatEvent",
+            Some(YouTubeChatMessageType::MembershipGifting) => "membershipGiftingEvent",
+            Some(YouTubeChatMessageType::GiftMembershipReceived) => "giftMembershipReceivedEvent",
+            _ => "newSponsorEvent",
+        };
+        InsertLivechatMembershipEvent {
+            youtube_id: msg.message_id.clone(),
+            channel_id: msg.channel_id.clone(),
+            display_name: msg.display_name.clone(),
+            event_type: event_type.to_string(),
+            member_level_name: Some(details.member_level_name).filter(|s| !s.is_empty()),
+            member_month: Some(details.member_month as i32).filter(|m| *m > 0),
+            message: msg.message.clone(),
+            sent_at: sent_at_chrono,
+            received_at: received_at_chrono,
+            gift_count: Some(details.gift_count).filter(|count| *count > 0),
+            gifter_channel_id: Some(details.gifter_channel_id).filter(|s| !s.is_empty()),
+            gifting_event_id: Some(details.gifting_event_id).filter(|s| !s.is_empty()),
+        }
+    }
+}
+
+#[derive(Queryable)]
+pub struct LivechatBan {
+    pub ban_id: i32,
+    pub youtube_id: String,
+    pub banned_channel_id: String,
+    pub banned_display_name: String,
+    pub ban_type: String,
+    pub ban_duration_seconds: Option<i64>,
+    pub moderator_channel_id: String,
+    pub moderator_display_name: String,
+    pub banned_at: NaiveDateTime,
+    pub received_at: NaiveDateTime,
+    pub tenant: String,
+    pub broadcast_id: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name = "livechat_bans"]
+pub struct InsertLivechatBan {
+    pub youtube_id: String,
+    pub banned_channel_id: String,
+    pub banned_display_name: String,
+    pub ban_type: String,
+    pub ban_duration_seconds: Option<i64>,
+    pub moderator_channel_id: String,
+    pub moderator_display_name: String,
+    pub banned_at: NaiveDateTime,
+    pub received_at: NaiveDateTime,
+}
+
+#[derive(Queryable)]
+pub struct Broadcast {
+    pub broadcast_id: i32,
+    pub youtube_id: String,
+    pub livechat_id: String,
+    pub title: String,
+    pub scheduled_start_at: Option<NaiveDateTime>,
+    pub scheduled_end_at: Option<NaiveDateTime>,
+    pub actual_start_at: Option<NaiveDateTime>,
+    pub actual_end_at: Option<NaiveDateTime>,
+    pub life_cycle_status: Option<String>,
+    pub tenant: String,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[table_name = "broadcasts"]
+pub struct InsertBroadcast {
+    pub youtube_id: String,
+    pub livechat_id: String,
+    pub title: String,
+    pub scheduled_start_at: Option<NaiveDateTime>,
+    pub scheduled_end_at: Option<NaiveDateTime>,
+    pub actual_start_at: Option<NaiveDateTime>,
+    pub actual_end_at: Option<NaiveDateTime>,
+    pub life_cycle_status: Option<String>,
+}
+
+/// Parses an RFC 3339 timestamp returned by the YouTube API
+pub fn parse_youtube_time(time: &Option<String>) -> Option<NaiveDateTime> {
+    time.as_ref()
+        .and_then(|t| chrono::DateTime::parse_from_rfc3339(t.as_str()).ok())
+        .map(|t| t.naive_utc())
+}
+
+impl From<&LiveBroadcast> for InsertBroadcast {
+    fn from(broadcast: &LiveBroadcast) -> Self {
+        let snippet = broadcast.snippet.clone().unwrap_or_default();
+        InsertBroadcast {
+            youtube_id: broadcast.id.clone().unwrap_or_default(),
+            scheduled_start_at: parse_youtube_time(&snippet.scheduled_start_time),
+            scheduled_end_at: parse_youtube_time(&snippet.scheduled_end_time),
+            actual_start_at: parse_youtube_time(&snippet.actual_start_time),
+            actual_end_at: parse_youtube_time(&snippet.actual_end_time),
+            livechat_id: snippet.live_chat_id.unwrap_or_default(),
+            title: snippet.title.unwrap_or_default(),
+            life_cycle_status: None,
+        }
+    }
+}
+
+#[derive(Queryable)]
+pub struct BroadcastStateChange {
+    pub state_change_id: i32,
+    pub broadcast_id: i32,
+    pub previous_status: String,
+    pub status: String,
+    pub changed_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "broadcast_state_changes"]
+pub struct InsertBroadcastStateChange {
+    pub broadcast_id: i32,
+    pub previous_status: String,
+    pub status: String,
+    pub changed_at: NaiveDateTime,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[table_name = "oauth_tokens"]
+pub struct InsertOauthToken {
+    pub tenant: String,
+    pub account: String,
+    pub nonce: Vec<u8>,
+    pub encrypted_token: Vec<u8>,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Queryable)]
+pub struct QuotaUsage {
+    pub usage_id: i32,
+    pub day: NaiveDate,
+    pub tenant: String,
+    pub account: String,
+    pub method: String,
+    pub calls: i32,
+    pub units: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "quota_usage"]
+pub struct InsertQuotaUsage {
+    pub day: NaiveDate,
+    pub tenant: String,
+    pub account: String,
+    pub method: String,
+    pub calls: i32,
+    pub units: i32,
+}
+
+#[derive(Queryable)]
+pub struct LivechatCursor {
+    pub cursor_id: i32,
+    pub tenant: String,
+    pub livechat_id: String,
+    pub page_token: Option<String>,
+    pub last_message_id: Option<String>,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "livechat_cursors"]
+pub struct InsertLivechatCursor {
+    pub tenant: String,
+    pub livechat_id: String,
+    pub page_token: Option<String>,
+    pub last_message_id: Option<String>,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Clone)]
+pub struct OutboundMessage {
+    pub outbound_message_id: i32,
+    pub tenant: String,
+    pub idempotency_key: String,
+    pub livechat_id: String,
+    pub message: String,
+    pub priority: String,
+    pub state: String,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub next_attempt_at: NaiveDateTime,
+    pub queued_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub youtube_message_id: Option<String>,
+    pub published_at: Option<NaiveDateTime>,
+    pub part: i32,
+    pub sender: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "outbound_messages"]
+pub struct InsertOutboundMessage {
+    pub tenant: String,
+    pub idempotency_key: String,
+    pub livechat_id: String,
+    pub message: String,
+    pub priority: String,
+    pub state: String,
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub queued_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub part: i32,
+    pub sender: String,
+}
+
+#[derive(Queryable)]
+pub struct ChatTimer {
+    pub timer_id: i32,
+    pub tenant: String,
+    pub name: String,
+    pub message: String,
+    pub cron_schedule: Option<String>,
+    pub interval_seconds: Option<i32>,
+    pub min_messages: i32,
+    pub run_without_live_broadcast: bool,
+    pub paused: bool,
+    pub last_run_at: Option<NaiveDateTime>,
+    pub next_run_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "chat_timers"]
+pub struct InsertChatTimer {
+    pub tenant: String,
+    pub name: String,
+    pub message: String,
+    pub cron_schedule: Option<String>,
+    pub interval_seconds: Option<i32>,
+    pub min_messages: i32,
+    pub run_without_live_broadcast: bool,
+    pub paused: bool,
+    pub next_run_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
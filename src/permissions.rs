@@ -0,0 +1,114 @@
+use std::env;
+
+use ring::constant_time::verify_slices_are_equal;
+use tonic::Status;
+
+use crate::tenant::tenant_env_var;
+use crate::youtube_service::AuthAccount;
+
+/// The request metadata clients identify themselves with
+pub const CLIENT_TOKEN_METADATA_KEY: &str = "x-client-token";
+
+/// Returns the client token the request carries, empty if there is none
+fn client_token<T>(request: &tonic::Request<T>) -> &str {
+    request
+        .metadata()
+        .get(CLIENT_TOKEN_METADATA_KEY)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// Compares the tokens in constant time, so a client can't guess a token by timing the responses
+fn token_matches(token: &str, client_token: &str) -> bool {
+    verify_slices_are_equal(token.as_bytes(), client_token.as_bytes()).is_ok()
+}
+
+/// The identities clients may send messages as, by the token they identify with
+#[derive(Clone, Debug, Default)]
+pub struct SenderPermissions {
+    clients: Vec<(String, Vec<AuthAccount>)>,
+}
+
+/// Reads the sender permissions of the tenant from YTS_SENDER_CLIENTS, a comma separated list of tokens
+/// with the identities the client may use, e.g. "token1=bot+streamer,token2=bot".
+/// Clients without a listed token may only send as the bot. Returns an error if an identity is unknown.
+pub fn sender_permissions_from_env(tenant: &str) -> Result<SenderPermissions, String> {
+    let clients = tenant_env_var(tenant, "SENDER_CLIENTS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|client| client.trim().split_once('='))
+        .filter(|(token, _)| !token.trim().is_empty())
+        .map(|(token, senders)| {
+            let senders = senders
+                .split('+')
+                .map(|sender| match sender.trim() {
+                    "bot" => Ok(AuthAccount::Bot),
+                    "streamer" => Ok(AuthAccount::Streamer),
+                    other => Err(format!("Unknown sender {} in YTS_SENDER_CLIENTS", other)),
+                })
+                .collect::<Result<Vec<AuthAccount>, String>>()?;
+            Ok((token.trim().to_string(), senders))
+        })
+        .collect::<Result<Vec<(String, Vec<AuthAccount>)>, String>>()?;
+    Ok(SenderPermissions { clients })
+}
+
+impl SenderPermissions {
+    /// Checks whether the client of the request may send messages as the identity
+    pub fn authorize<T>(
+        &self,
+        request: &tonic::Request<T>,
+        sender: AuthAccount,
+    ) -> Result<(), Status> {
+        let client_token = client_token(request);
+        let senders = self
+            .clients
+            .iter()
+            .find(|(token, _)| token_matches(token, client_token))
+            .map(|(_, senders)| senders.as_slice())
+            .unwrap_or(&[AuthAccount::Bot]);
+        if !senders.contains(&sender) {
+            return Err(Status::new(
+                tonic::Code::PermissionDenied,
+                format!("This client may not send messages as the {:?}", sender),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The clients that may administrate the service, e.g. see the codes that authenticate its accounts
+#[derive(Clone, Debug, Default)]
+pub struct AdminClients {
+    tokens: Vec<String>,
+}
+
+/// Reads the tokens of the admin clients from YTS_ADMIN_CLIENTS, a comma separated list.
+/// The setting covers every tenant. Without it, no client is an admin.
+pub fn admin_clients_from_env() -> AdminClients {
+    let tokens = env::var("YTS_ADMIN_CLIENTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+        .collect();
+    AdminClients { tokens }
+}
+
+impl AdminClients {
+    /// Checks whether the client of the request is an admin
+    pub fn authorize<T>(&self, request: &tonic::Request<T>) -> Result<(), Status> {
+        let client_token = client_token(request);
+        if !self
+            .tokens
+            .iter()
+            .any(|token| token_matches(token, client_token))
+        {
+            return Err(Status::new(
+                tonic::Code::PermissionDenied,
+                "This call requires an admin client token",
+            ));
+        }
+        Ok(())
+    }
+}
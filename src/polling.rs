@@ -0,0 +1,185 @@
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::tenant::tenant_env_var;
+
+/// The kinds of errors polling a chat can run into, each one is retried differently
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The quota of the project or the rate limit is used up
+    Quota,
+    /// The token was rejected, it is refreshed or the account has to be authenticated again
+    Auth,
+    /// The chat does not exist anymore or was ended or disabled
+    NotFound,
+    /// YouTube or the network had a problem, which usually goes away by itself
+    Server,
+    /// The request itself is wrong, sending it again won't help
+    Client,
+}
+
+/// Sorts an error of the YouTube API into the class that decides how it is retried
+pub fn classify_google_error(error: &google_youtube3::Error) -> ErrorClass {
+    match error {
+        google_youtube3::Error::BadRequest(bad_request) => {
+            let has_reason = |reasons: &[&str]| {
+                bad_request
+                    .error
+                    .errors
+                    .iter()
+                    .any(|error| reasons.contains(&error.reason.as_str()))
+            };
+            if has_reason(&[
+                "quotaExceeded",
+                "dailyLimitExceeded",
+                "rateLimitExceeded",
+                "userRateLimitExceeded",
+            ]) || bad_request.error.code == 429
+            {
+                return ErrorClass::Quota;
+            }
+            if has_reason(&["liveChatEnded", "liveChatDisabled", "liveChatNotFound"]) {
+                return ErrorClass::NotFound;
+            }
+            match bad_request.error.code {
+                401 => ErrorClass::Auth,
+                404 | 410 => ErrorClass::NotFound,
+                500..=599 => ErrorClass::Server,
+                _ => ErrorClass::Client,
+            }
+        }
+        google_youtube3::Error::MissingToken(_) => ErrorClass::Auth,
+        google_youtube3::Error::Failure(failure) => {
+            if failure.status().is_server_error() {
+                ErrorClass::Server
+            } else {
+                ErrorClass::Client
+            }
+        }
+        google_youtube3::Error::HttpError(_)
+        | google_youtube3::Error::Io(_)
+        | google_youtube3::Error::JsonDecodeError(_, _) => ErrorClass::Server,
+        _ => ErrorClass::Client,
+    }
+}
+
+/// The settings that decide how often chats are polled and how failed polls are retried
+#[derive(Clone, Debug)]
+pub struct PollingPolicy {
+    /// The first delay after a server or network error, it doubles with every further failure
+    pub backoff: Duration,
+    /// The first delay after running out of quota
+    pub quota_backoff: Duration,
+    /// The first delay after the token was rejected
+    pub auth_backoff: Duration,
+    /// No delay grows beyond this
+    pub max_backoff: Duration,
+    /// How many server or network errors in a row are retried before giving up on the chat
+    pub max_retries: u32,
+    /// The chat counts as idle once it had no messages for this long
+    pub idle_after: Duration,
+    /// The least time between polls of an idle chat
+    pub idle_interval: Duration,
+    /// How long to wait before following a chat again after giving up on it
+    pub refollow_delay: Duration,
+    /// How long to wait before selecting the broadcasts again when YouTube could not be asked
+    pub waiting_interval: Duration,
+}
+
+impl PollingPolicy {
+    /// Returns how long to wait before the next attempt after the given number of errors in a row, the last one of the class.
+    /// The delay doubles with every error, up to the maximum, and is jittered. Returns None if the error is not worth retrying.
+    pub fn backoff_delay(&self, error_class: ErrorClass, failures: u32) -> Option<Duration> {
+        let first_delay = match error_class {
+            ErrorClass::Quota => self.quota_backoff,
+            ErrorClass::Auth => self.auth_backoff,
+            ErrorClass::Server if failures <= self.max_retries => self.backoff,
+            ErrorClass::Server | ErrorClass::NotFound | ErrorClass::Client => return None,
+        };
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        let delay = first_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+        Some(jitter(delay))
+    }
+}
+
+/// Reads a duration in milliseconds of the tenant from the environment
+fn duration_from_env(tenant: &str, name: &str, default_millis: u64) -> Duration {
+    let millis = tenant_env_var(tenant, name)
+        .and_then(|millis| millis.parse().ok())
+        .unwrap_or(default_millis);
+    Duration::from_millis(millis)
+}
+
+/// Reads the polling policy of the tenant from the environment, all durations are in milliseconds.
+/// YTS_POLL_BACKOFF, 1000 by default, YTS_POLL_QUOTA_BACKOFF, 60000 by default, and YTS_POLL_AUTH_BACKOFF, 30000 by default, are the first delays after an error.
+/// YTS_POLL_MAX_BACKOFF caps them, 900000 by default. YTS_POLL_MAX_RETRIES is how many server errors in a row are retried, 5 by default.
+/// Chats without messages for YTS_POLL_IDLE_AFTER, 120000 by default, are polled at most every YTS_POLL_IDLE_INTERVAL, 10000 by default.
+/// Chats that failed are followed again after YTS_POLL_REFOLLOW_DELAY, 10000 by default,
+/// and broadcasts that couldn't be selected are selected again after YTS_POLL_WAITING_INTERVAL, 30000 by default.
+pub fn polling_policy_from_env(tenant: &str) -> PollingPolicy {
+    PollingPolicy {
+        backoff: duration_from_env(tenant, "POLL_BACKOFF", 1000),
+        quota_backoff: duration_from_env(tenant, "POLL_QUOTA_BACKOFF", 60000),
+        auth_backoff: duration_from_env(tenant, "POLL_AUTH_BACKOFF", 30000),
+        max_backoff: duration_from_env(tenant, "POLL_MAX_BACKOFF", 900000),
+        max_retries: tenant_env_var(tenant, "POLL_MAX_RETRIES")
+            .and_then(|retries| retries.parse().ok())
+            .unwrap_or(5),
+        idle_after: duration_from_env(tenant, "POLL_IDLE_AFTER", 120000),
+        idle_interval: duration_from_env(tenant, "POLL_IDLE_INTERVAL", 10000),
+        refollow_delay: duration_from_env(tenant, "POLL_REFOLLOW_DELAY", 10000),
+        waiting_interval: duration_from_env(tenant, "POLL_WAITING_INTERVAL", 30000),
+    }
+}
+
+/// Spreads the delay randomly between half of it and all of it,
+/// so chats that failed at the same time don't retry at the same time
+pub fn jitter(delay: Duration) -> Duration {
+    let millis = delay.as_millis() as u64;
+    if millis < 2 {
+        return delay;
+    }
+    Duration::from_millis(rand::thread_rng().gen_range(millis / 2..=millis))
+}
+
+/// Decides when a chat is polled next, based on the interval YouTube asks for, how busy the chat is and the errors in a row
+pub struct PollingScheduler {
+    policy: PollingPolicy,
+    /// The errors since the last successful poll
+    failures: u32,
+    last_message_at: Instant,
+}
+
+impl PollingScheduler {
+    pub fn new(policy: PollingPolicy) -> Self {
+        PollingScheduler {
+            policy,
+            failures: 0,
+            last_message_at: Instant::now(),
+        }
+    }
+
+    /// Returns how long to wait after a successful poll that returned the given number of messages.
+    /// The interval is the one YouTube asked for, it is stretched while the chat is idle.
+    pub fn next_poll(&mut self, interval: Duration, message_count: usize) -> Duration {
+        self.failures = 0;
+        let now = Instant::now();
+        if message_count > 0 {
+            self.last_message_at = now;
+        }
+        if now.duration_since(self.last_message_at) >= self.policy.idle_after {
+            return interval.max(self.policy.idle_interval);
+        }
+        interval
+    }
+
+    /// Returns how long to wait before retrying after an error of the class, or None to give up on the chat
+    pub fn retry_delay(&mut self, error_class: ErrorClass) -> Option<Duration> {
+        self.failures += 1;
+        self.policy.backoff_delay(error_class, self.failures)
+    }
+}
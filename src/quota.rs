@@ -0,0 +1,216 @@
+use std::env;
+use std::sync::Arc;
+
+use chrono::{FixedOffset, NaiveDate, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use log::{error, info, warn};
+use r2d2::Pool;
+use tokio::sync::RwLock;
+
+use crate::auth::AccountId;
+use crate::models::{InsertQuotaUsage, QuotaUsage};
+use crate::schema;
+use crate::youtube_service::{AuthAccount, QuotaStatus, QuotaUsageEntry};
+
+/// Returns the quota units a call of the YouTube API method costs, see https://developers.google.com/youtube/v3/determine_quota_cost
+pub fn quota_cost(method: &str) -> i32 {
+    match method {
+        "liveBroadcasts.list" | "liveChatMessages.list" => 5,
+        "liveChatMessages.insert"
+        | "liveChatMessages.delete"
+        | "liveChatBans.insert"
+        | "liveChatBans.delete"
+        | "liveChatModerators.list"
+        | "liveChatModerators.insert"
+        | "liveChatModerators.delete" => 50,
+        _ => {
+            warn!("Unknown quota cost of {}, assuming 50 units", method);
+            50
+        }
+    }
+}
+
+/// Returns the current quota day. YouTube resets the quota at midnight Pacific Time,
+/// this uses Pacific Standard Time, so during daylight saving time the day changes an hour late.
+pub fn quota_day() -> NaiveDate {
+    Utc::now()
+        .with_timezone(&FixedOffset::west(8 * 3600))
+        .date()
+        .naive_local()
+}
+
+/// Keeps track of the quota units all tenants use, which come from the same daily budget of the Google project.
+/// Readers are authenticated through their own Google projects, their calls are recorded but don't use up the budget.
+#[derive(Clone)]
+pub struct QuotaTracker {
+    database_connection: Pool<ConnectionManager<PgConnection>>,
+    budget: i64,
+    threshold: i64,
+    /// The units used on the quota day
+    usage: Arc<RwLock<(NaiveDate, i64)>>,
+}
+
+/// Reads the quota budget from the environment and loads what was already used today.
+/// YTS_QUOTA_BUDGET is the daily budget in units, 10000 by default.
+/// YTS_QUOTA_THRESHOLD is the percentage of the budget above which the service saves quota, 80 by default.
+pub fn quota_tracker_from_env(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+) -> QuotaTracker {
+    let budget: i64 = env::var("YTS_QUOTA_BUDGET")
+        .ok()
+        .and_then(|budget| budget.parse().ok())
+        .unwrap_or(10000);
+    let threshold_percent: i64 = env::var("YTS_QUOTA_THRESHOLD")
+        .ok()
+        .and_then(|threshold| threshold.parse().ok())
+        .unwrap_or(80);
+    let day = quota_day();
+    let used = match load_usage(database_connection, day) {
+        Ok(usage) => usage
+            .iter()
+            .filter(|usage| !is_reader(&usage.account))
+            .map(|usage| usage.units as i64)
+            .sum(),
+        Err(e) => {
+            error!("Unable to load the quota usage: {}", e);
+            0
+        }
+    };
+    info!("{} of {} quota units used today", used, budget);
+    QuotaTracker {
+        database_connection: database_connection.clone(),
+        budget,
+        threshold: budget * threshold_percent / 100,
+        usage: Arc::new(RwLock::new((day, used))),
+    }
+}
+
+/// Whether the account stored with the usage is a reader, which has a budget of its own
+fn is_reader(account_name: &str) -> bool {
+    matches!(
+        AccountId::from_name(account_name),
+        Some(AccountId {
+            account: AuthAccount::Reader,
+            ..
+        })
+    )
+}
+
+/// Reads the quota usage of the day from the database
+fn load_usage(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    usage_day: NaiveDate,
+) -> Result<Vec<QuotaUsage>, Box<dyn std::error::Error>> {
+    use schema::quota_usage::dsl::*;
+    let usage = quota_usage
+        .filter(day.eq(usage_day))
+        .order((tenant, account, method))
+        .load::<QuotaUsage>(&database_connection.get()?)?;
+    Ok(usage)
+}
+
+impl QuotaTracker {
+    /// Records a call of the YouTube API method by the account of the tenant
+    pub async fn record(&self, tenant_name: &str, account: AccountId, method_name: &str) {
+        let cost = quota_cost(method_name);
+        let today = quota_day();
+        // Readers use the quota of their own projects
+        if account.account != AuthAccount::Reader {
+            let mut usage = self.usage.write().await;
+            // The quota was reset
+            if usage.0 != today {
+                *usage = (today, 0);
+            }
+            let was_throttled = usage.1 >= self.threshold;
+            usage.1 += cost as i64;
+            if !was_throttled && usage.1 >= self.threshold {
+                warn!(
+                    "{} of {} quota units used, saving quota from now on",
+                    usage.1, self.budget
+                );
+            }
+        }
+
+        let insert_usage = InsertQuotaUsage {
+            day: today,
+            tenant: tenant_name.to_string(),
+            account: account.to_string(),
+            method: method_name.to_string(),
+            calls: 1,
+            units: cost,
+        };
+        if let Err(e) = self.store(&insert_usage) {
+            error!("Error while storing quota usage: {}", e);
+        }
+    }
+
+    /// Adds the usage to the usage of its day, method and account in the database
+    fn store(&self, insert_usage: &InsertQuotaUsage) -> Result<(), Box<dyn std::error::Error>> {
+        use schema::quota_usage::dsl::*;
+        diesel::insert_into(quota_usage)
+            .values(insert_usage)
+            .on_conflict((day, tenant, account, method))
+            .do_update()
+            .set((
+                calls.eq(calls + insert_usage.calls),
+                units.eq(units + insert_usage.units),
+            ))
+            .execute(&self.database_connection.get()?)?;
+        Ok(())
+    }
+
+    /// Returns the units used on the current quota day
+    pub async fn used_today(&self) -> i64 {
+        let usage = self.usage.read().await;
+        if usage.0 != quota_day() {
+            return 0;
+        }
+        usage.1
+    }
+
+    /// Whether the threshold is reached and quota should be saved
+    pub async fn is_throttled(&self) -> bool {
+        self.used_today().await >= self.threshold
+    }
+
+    /// Returns by how much polling intervals should be stretched.
+    /// Above the threshold, this grows linearly up to 10 times the interval once the budget is used up.
+    pub async fn polling_factor(&self) -> f64 {
+        let used = self.used_today().await;
+        if used < self.threshold || self.budget <= self.threshold {
+            return 1.0;
+        }
+        let over_threshold = (used - self.threshold) as f64 / (self.budget - self.threshold) as f64;
+        1.0 + 9.0 * over_threshold.min(1.0)
+    }
+
+    /// Returns the usage of the current quota day per tenant, account and method
+    pub async fn status(&self) -> Result<QuotaStatus, Box<dyn std::error::Error>> {
+        let day = quota_day();
+        let units_used = self.used_today().await;
+        let usage = load_usage(&self.database_connection, day)?
+            .into_iter()
+            .map(|usage| {
+                let account = AccountId::from_name(&usage.account).unwrap_or(AccountId::BOT);
+                QuotaUsageEntry {
+                    tenant: usage.tenant,
+                    account: account.account as i32,
+                    reader: account.reader,
+                    method: usage.method,
+                    calls: usage.calls,
+                    units: usage.units,
+                }
+            })
+            .collect();
+        Ok(QuotaStatus {
+            day: day.format("%Y-%m-%d").to_string(),
+            units_used,
+            budget: self.budget,
+            threshold: self.threshold,
+            throttled: units_used >= self.threshold,
+            usage,
+        })
+    }
+}
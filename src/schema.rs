@@ -1,6 +1,99 @@
 // DO NOT TOUCH THIS FILE!
 // THIS FILE IS AUTO-GENERATED BY DIESEL!
 
+table! {
+    broadcast_state_changes (state_change_id) {
+        state_change_id -> Int4,
+        broadcast_id -> Int4,
+        previous_status -> Varchar,
+        status -> Varchar,
+        changed_at -> Timestamp,
+    }
+}
+
+table! {
+    broadcasts (broadcast_id) {
+        broadcast_id -> Int4,
+        youtube_id -> Varchar,
+        livechat_id -> Varchar,
+        title -> Varchar,
+        scheduled_start_at -> Nullable<Timestamp>,
+        scheduled_end_at -> Nullable<Timestamp>,
+        actual_start_at -> Nullable<Timestamp>,
+        actual_end_at -> Nullable<Timestamp>,
+        life_cycle_status -> Nullable<Varchar>,
+        tenant -> Varchar,
+    }
+}
+
+table! {
+    chat_timers (timer_id) {
+        timer_id -> Int4,
+        tenant -> Varchar,
+        name -> Varchar,
+        message -> Text,
+        cron_schedule -> Nullable<Varchar>,
+        interval_seconds -> Nullable<Int4>,
+        min_messages -> Int4,
+        run_without_live_broadcast -> Bool,
+        paused -> Bool,
+        last_run_at -> Nullable<Timestamp>,
+        next_run_at -> Timestamp,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    livechat_bans (ban_id) {
+        ban_id -> Int4,
+        youtube_id -> Varchar,
+        banned_channel_id -> Varchar,
+        banned_display_name -> Varchar,
+        ban_type -> Varchar,
+        ban_duration_seconds -> Nullable<Int8>,
+        moderator_channel_id -> Varchar,
+        moderator_display_name -> Varchar,
+        banned_at -> Timestamp,
+        received_at -> Timestamp,
+        tenant -> Varchar,
+        broadcast_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    livechat_cursors (cursor_id) {
+        cursor_id -> Int4,
+        tenant -> Varchar,
+        livechat_id -> Varchar,
+        page_token -> Nullable<Varchar>,
+        last_message_id -> Nullable<Varchar>,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    livechat_membership_events (membership_event_id) {
+        membership_event_id -> Int4,
+        youtube_id -> Varchar,
+        channel_id -> Varchar,
+        display_name -> Varchar,
+        event_type -> Varchar,
+        member_level_name -> Nullable<Varchar>,
+        member_month -> Nullable<Int4>,
+        message -> Text,
+        sent_at -> Timestamp,
+        received_at -> Timestamp,
+        tenant -> Varchar,
+        gift_count -> Nullable<Int4>,
+        gifter_channel_id -> Nullable<Varchar>,
+        gifting_event_id -> Nullable<Varchar>,
+        deleted_at -> Nullable<Timestamp>,
+        deleted_by_channel_id -> Nullable<Varchar>,
+        broadcast_id -> Nullable<Int4>,
+    }
+}
+
 table! {
     livechat_messages (message_id) {
         message_id -> Int4,
@@ -10,5 +103,103 @@ table! {
         message -> Text,
         sent_at -> Timestamp,
         received_at -> Timestamp,
+        is_chat_owner -> Bool,
+        is_chat_moderator -> Bool,
+        is_chat_sponsor -> Bool,
+        is_verified -> Bool,
+        profile_image_url -> Nullable<Varchar>,
+        deleted_at -> Nullable<Timestamp>,
+        deleted_by_channel_id -> Nullable<Varchar>,
+        broadcast_id -> Nullable<Int4>,
+        livechat_id -> Nullable<Varchar>,
+        tenant -> Varchar,
+        sent_by_service -> Bool,
     }
 }
+
+table! {
+    livechat_super_chats (super_chat_id) {
+        super_chat_id -> Int4,
+        youtube_id -> Varchar,
+        channel_id -> Varchar,
+        display_name -> Varchar,
+        event_type -> Varchar,
+        amount_micros -> Int8,
+        currency -> Varchar,
+        amount_display_string -> Varchar,
+        tier -> Int4,
+        sticker_id -> Nullable<Varchar>,
+        sticker_alt_text -> Nullable<Varchar>,
+        user_comment -> Nullable<Text>,
+        sent_at -> Timestamp,
+        received_at -> Timestamp,
+        tenant -> Varchar,
+        deleted_at -> Nullable<Timestamp>,
+        deleted_by_channel_id -> Nullable<Varchar>,
+        broadcast_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    oauth_tokens (token_id) {
+        token_id -> Int4,
+        tenant -> Varchar,
+        account -> Varchar,
+        nonce -> Bytea,
+        encrypted_token -> Bytea,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    outbound_messages (outbound_message_id) {
+        outbound_message_id -> Int4,
+        tenant -> Varchar,
+        idempotency_key -> Varchar,
+        livechat_id -> Varchar,
+        message -> Text,
+        priority -> Varchar,
+        state -> Varchar,
+        attempts -> Int4,
+        error -> Nullable<Text>,
+        next_attempt_at -> Timestamp,
+        queued_at -> Timestamp,
+        updated_at -> Timestamp,
+        youtube_message_id -> Nullable<Varchar>,
+        published_at -> Nullable<Timestamp>,
+        part -> Int4,
+        sender -> Varchar,
+    }
+}
+
+table! {
+    quota_usage (usage_id) {
+        usage_id -> Int4,
+        day -> Date,
+        tenant -> Varchar,
+        account -> Varchar,
+        method -> Varchar,
+        calls -> Int4,
+        units -> Int4,
+    }
+}
+
+joinable!(broadcast_state_changes -> broadcasts (broadcast_id));
+joinable!(livechat_bans -> broadcasts (broadcast_id));
+joinable!(livechat_membership_events -> broadcasts (broadcast_id));
+joinable!(livechat_messages -> broadcasts (broadcast_id));
+joinable!(livechat_super_chats -> broadcasts (broadcast_id));
+
+allow_tables_to_appear_in_same_query!(
+    broadcast_state_changes,
+    broadcasts,
+    chat_timers,
+    livechat_bans,
+    livechat_cursors,
+    livechat_membership_events,
+    livechat_messages,
+    livechat_super_chats,
+    oauth_tokens,
+    outbound_messages,
+    quota_usage,
+);
@@ -0,0 +1,250 @@
+use std::sync::Arc;
+
+use chrono::naive::MAX_DATETIME;
+use chrono::NaiveDateTime;
+use google_youtube3::api::LiveBroadcast;
+use log::info;
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio::sync::RwLock;
+
+use crate::broadcast::broadcast_status_of;
+use crate::models::parse_youtube_time;
+use crate::tenant::{tenant_env_var, Tenant};
+use crate::youtube::{get_broadcasts_by_id, list_broadcasts};
+use crate::youtube_service::{BroadcastSelection, BroadcastSelectionPolicy, BroadcastStatus};
+
+/// The policy used to choose the broadcast whose livechat is followed, can be changed at runtime
+#[derive(Clone)]
+pub struct SharedBroadcastSelection {
+    selection: Arc<RwLock<BroadcastSelection>>,
+    changes_tx: Sender<BroadcastSelection>,
+}
+
+impl SharedBroadcastSelection {
+    pub fn new(selection: BroadcastSelection) -> Self {
+        let (changes_tx, _) = tokio::sync::broadcast::channel(4);
+        SharedBroadcastSelection {
+            selection: Arc::new(RwLock::new(selection)),
+            changes_tx,
+        }
+    }
+
+    pub async fn get(&self) -> BroadcastSelection {
+        self.selection.read().await.clone()
+    }
+
+    /// Replaces the selection and notifies everyone who subscribed to changes
+    pub async fn set(&self, selection: BroadcastSelection) {
+        *self.selection.write().await = selection.clone();
+        // Nobody listening is not an error
+        let _ = self.changes_tx.send(selection);
+    }
+
+    pub fn subscribe(&self) -> Receiver<BroadcastSelection> {
+        self.changes_tx.subscribe()
+    }
+}
+
+/// Reads the selection policy of the tenant from the environment.
+/// YTS_BROADCAST_SELECTION is one of "first", "id", "title", "newest", "persistent", "upcoming" or "all",
+/// "id" needs YTS_BROADCAST_ID and "title" needs YTS_BROADCAST_TITLE_PATTERN to be set as well.
+/// Each of them can be overridden per tenant, see `tenant_env_var`. Returns an error if the policy is unknown or lacks what it needs.
+pub fn broadcast_selection_from_env(tenant: &str) -> Result<BroadcastSelection, String> {
+    let policy = match tenant_env_var(tenant, "BROADCAST_SELECTION").as_deref() {
+        Some("id") => BroadcastSelectionPolicy::ById,
+        Some("title") => BroadcastSelectionPolicy::TitlePattern,
+        Some("newest") => BroadcastSelectionPolicy::NewestStart,
+        Some("persistent") => BroadcastSelectionPolicy::Persistent,
+        Some("upcoming") => BroadcastSelectionPolicy::Upcoming,
+        Some("all") => BroadcastSelectionPolicy::AllActive,
+        Some("first") | Some("") | None => BroadcastSelectionPolicy::FirstActive,
+        Some(other) => return Err(format!("Unknown broadcast selection policy {}", other)),
+    };
+    let selection = BroadcastSelection {
+        policy: policy as i32,
+        broadcast_id: tenant_env_var(tenant, "BROADCAST_ID").unwrap_or_default(),
+        title_pattern: tenant_env_var(tenant, "BROADCAST_TITLE_PATTERN").unwrap_or_default(),
+    };
+    if let Err(e) = validate_broadcast_selection(&selection) {
+        return Err(format!("invalid broadcast selection: {}", e));
+    }
+    info!("Selecting broadcasts of tenant {} by {:?}", tenant, policy);
+    Ok(selection)
+}
+
+/// Checks that a policy comes with everything it needs to select a broadcast
+pub fn validate_broadcast_selection(selection: &BroadcastSelection) -> Result<(), &'static str> {
+    match BroadcastSelectionPolicy::from_i32(selection.policy) {
+        None => Err("unknown broadcast selection policy"),
+        Some(BroadcastSelectionPolicy::ById) if selection.broadcast_id.is_empty() => {
+            Err("selecting by id requires a broadcast id")
+        }
+        Some(BroadcastSelectionPolicy::TitlePattern) if selection.title_pattern.is_empty() => {
+            Err("selecting by title requires a title pattern")
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+/// Checks whether the title matches the pattern, ignoring case. A `*` in the pattern matches any text.
+pub fn title_matches(title: &str, pattern: &str) -> bool {
+    let title = title.to_lowercase();
+    let pattern = pattern.to_lowercase();
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return title == pattern;
+    }
+    // The first part has to be at the start, the last part at the end and everything in between in order
+    let first = parts[0];
+    let last = parts[parts.len() - 1];
+    if !title.starts_with(first) {
+        return false;
+    }
+    let mut rest = &title[first.len()..];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Only broadcasts with a livechat can be followed
+fn has_livechat(broadcast: &LiveBroadcast) -> bool {
+    broadcast
+        .snippet
+        .as_ref()
+        .and_then(|snippet| snippet.live_chat_id.as_ref())
+        .is_some()
+}
+
+fn actual_start_of(broadcast: &LiveBroadcast) -> Option<NaiveDateTime> {
+    parse_youtube_time(&broadcast.snippet.as_ref()?.actual_start_time)
+}
+
+fn scheduled_start_of(broadcast: &LiveBroadcast) -> Option<NaiveDateTime> {
+    parse_youtube_time(&broadcast.snippet.as_ref()?.scheduled_start_time)
+}
+
+/// Selects the broadcasts whose livechats should be followed according to the policy.
+/// Every policy but ALL_ACTIVE selects one broadcast at most.
+/// Returns None if YouTube could not be asked, the list is empty if nothing matches right now.
+pub async fn select_broadcasts(
+    tenant: &Tenant,
+    selection: &BroadcastSelection,
+) -> Option<Vec<LiveBroadcast>> {
+    let policy = BroadcastSelectionPolicy::from_i32(selection.policy)
+        .unwrap_or(BroadcastSelectionPolicy::FirstActive);
+    // Every policy chooses from a single list of broadcasts, so selecting costs one call
+    let broadcasts = match policy {
+        BroadcastSelectionPolicy::ById => {
+            get_broadcasts_by_id(tenant, &[selection.broadcast_id.clone()]).await?
+        }
+        BroadcastSelectionPolicy::Persistent => {
+            list_broadcasts(tenant, "all", "persistent").await?
+        }
+        BroadcastSelectionPolicy::Upcoming => list_broadcasts(tenant, "upcoming", "all").await?,
+        BroadcastSelectionPolicy::FirstActive
+        | BroadcastSelectionPolicy::AllActive
+        | BroadcastSelectionPolicy::TitlePattern
+        | BroadcastSelectionPolicy::NewestStart => list_broadcasts(tenant, "active", "all").await?,
+    };
+    let broadcasts = broadcasts.into_iter().filter(has_livechat);
+    if policy == BroadcastSelectionPolicy::AllActive {
+        return Some(broadcasts.collect());
+    }
+    Some(
+        select_broadcast(broadcasts, policy, selection)
+            .into_iter()
+            .collect(),
+    )
+}
+
+/// Chooses the broadcast whose livechat should be followed according to the policy among the listed ones.
+/// Returns None if none of them matches.
+fn select_broadcast(
+    mut broadcasts: impl Iterator<Item = LiveBroadcast>,
+    policy: BroadcastSelectionPolicy,
+    selection: &BroadcastSelection,
+) -> Option<LiveBroadcast> {
+    match policy {
+        BroadcastSelectionPolicy::FirstActive | BroadcastSelectionPolicy::AllActive => {
+            broadcasts.next()
+        }
+        // A completed broadcast has no chat to follow anymore
+        BroadcastSelectionPolicy::ById | BroadcastSelectionPolicy::Persistent => {
+            broadcasts.find(|broadcast| broadcast_status_of(broadcast) != BroadcastStatus::Complete)
+        }
+        BroadcastSelectionPolicy::TitlePattern => broadcasts
+            .filter(|broadcast| {
+                let title = broadcast
+                    .snippet
+                    .as_ref()
+                    .and_then(|snippet| snippet.title.as_deref())
+                    .unwrap_or_default();
+                title_matches(title, &selection.title_pattern)
+            })
+            // If several broadcasts match, the newest one wins
+            .max_by_key(actual_start_of),
+        BroadcastSelectionPolicy::NewestStart => broadcasts.max_by_key(actual_start_of),
+        // The broadcast that is scheduled to start next comes first, unscheduled ones last
+        BroadcastSelectionPolicy::Upcoming => {
+            broadcasts.min_by_key(|broadcast| scheduled_start_of(broadcast).unwrap_or(MAX_DATETIME))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_without_wildcard_matches_exactly() {
+        assert!(title_matches("Friday Stream", "friday stream"));
+        assert!(!title_matches("Friday Stream!", "friday stream"));
+        assert!(!title_matches("Friday", "friday stream"));
+    }
+
+    #[test]
+    fn wildcard_matches_any_text() {
+        assert!(title_matches("Friday Stream", "friday*"));
+        assert!(title_matches("Big Friday Stream", "*stream"));
+        assert!(title_matches("Big Friday Stream", "*friday*"));
+        assert!(title_matches("anything", "*"));
+        assert!(title_matches("", "*"));
+    }
+
+    #[test]
+    fn wildcard_parts_have_to_appear_in_order() {
+        assert!(title_matches("Karaoke night with guests", "karaoke*with*"));
+        assert!(!title_matches(
+            "With guests, karaoke night",
+            "karaoke*with*"
+        ));
+        assert!(!title_matches("Friday Stream", "stream*friday"));
+    }
+
+    #[test]
+    fn wildcard_parts_do_not_overlap() {
+        assert!(!title_matches("a", "a*a"));
+        assert!(title_matches("aa", "a*a"));
+        assert!(!title_matches("abc", "ab*bc"));
+        assert!(title_matches("abbc", "ab*bc"));
+    }
+
+    #[test]
+    fn selections_need_what_their_policy_uses() {
+        let by_id = BroadcastSelection {
+            policy: BroadcastSelectionPolicy::ById as i32,
+            ..Default::default()
+        };
+        assert!(validate_broadcast_selection(&by_id).is_err());
+        let by_title = BroadcastSelection {
+            policy: BroadcastSelectionPolicy::TitlePattern as i32,
+            title_pattern: "friday*".to_string(),
+            ..Default::default()
+        };
+        assert!(validate_broadcast_selection(&by_title).is_ok());
+    }
+}
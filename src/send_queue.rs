@@ -0,0 +1,513 @@
+use std::cmp::Reverse;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use google_youtube3::api::{LiveChatMessage, LiveChatMessageSnippet, LiveChatTextMessageDetails};
+use log::{debug, error, warn};
+use r2d2::Pool;
+use rand::Rng;
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio::sync::Notify;
+
+use crate::auth::{to_timestamp, AccountId};
+use crate::insert_own_message;
+use crate::log::log_google_errors;
+use crate::models::{parse_youtube_time, InsertOutboundMessage, OutboundMessage};
+use crate::polling::classify_google_error;
+use crate::schema;
+use crate::tenant::{tenant_env_var, Tenant};
+use crate::youtube_service::{
+    MessagePriority, SendState, SendStatus, YouTubeChatMessage, YouTubeChatMessageType,
+};
+
+/// Returns the name a message priority is stored with in the database
+pub fn priority_name(priority: MessagePriority) -> &'static str {
+    match priority {
+        MessagePriority::PriorityLow => "low",
+        MessagePriority::PriorityNormal => "normal",
+        MessagePriority::PriorityHigh => "high",
+    }
+}
+
+/// Parses a message priority stored in the database
+pub fn priority_from_name(name: &str) -> MessagePriority {
+    match name {
+        "low" => MessagePriority::PriorityLow,
+        "high" => MessagePriority::PriorityHigh,
+        _ => MessagePriority::PriorityNormal,
+    }
+}
+
+/// Messages with a higher rank are sent first
+fn priority_rank(priority: MessagePriority) -> u8 {
+    match priority {
+        MessagePriority::PriorityLow => 0,
+        MessagePriority::PriorityNormal => 1,
+        MessagePriority::PriorityHigh => 2,
+    }
+}
+
+/// Returns the name a send state is stored with in the database
+pub fn send_state_name(state: SendState) -> &'static str {
+    match state {
+        SendState::SendPending => "pending",
+        SendState::SendSent => "sent",
+        SendState::SendFailed => "failed",
+    }
+}
+
+/// Parses a send state stored in the database
+pub fn send_state_from_name(name: &str) -> SendState {
+    match name {
+        "sent" => SendState::SendSent,
+        "failed" => SendState::SendFailed,
+        _ => SendState::SendPending,
+    }
+}
+
+fn to_send_status(outbound_message: &OutboundMessage) -> SendStatus {
+    SendStatus {
+        idempotency_key: outbound_message.idempotency_key.clone(),
+        livechat_id: outbound_message.livechat_id.clone(),
+        message: outbound_message.message.clone(),
+        priority: priority_from_name(&outbound_message.priority) as i32,
+        state: send_state_from_name(&outbound_message.state) as i32,
+        attempts: outbound_message.attempts,
+        error: outbound_message.error.clone().unwrap_or_default(),
+        queued_at_timestamp: Some(to_timestamp(DateTime::from_utc(
+            outbound_message.queued_at,
+            Utc,
+        ))),
+        updated_at_timestamp: Some(to_timestamp(DateTime::from_utc(
+            outbound_message.updated_at,
+            Utc,
+        ))),
+        part: outbound_message.part,
+        sender: AccountId::from_name(&outbound_message.sender)
+            .unwrap_or(AccountId::BOT)
+            .account as i32,
+        youtube_message_id: outbound_message
+            .youtube_message_id
+            .clone()
+            .unwrap_or_default(),
+        published_at_timestamp: outbound_message
+            .published_at
+            .map(|published_at| to_timestamp(DateTime::from_utc(published_at, Utc))),
+    }
+}
+
+/// Returns a random key for requests that came without an idempotency key
+pub fn generate_idempotency_key() -> String {
+    let mut rng = rand::thread_rng();
+    (0..16)
+        .map(|_| format!("{:02x}", rng.gen::<u8>()))
+        .collect()
+}
+
+/// The messages of a tenant that wait to be sent, kept in the database so they survive a restart
+#[derive(Clone)]
+pub struct SendQueue {
+    database_connection: Pool<ConnectionManager<PgConnection>>,
+    tenant_name: String,
+    changes_tx: Sender<SendStatus>,
+    /// Wakes up the delivery when a message is queued
+    queued: Arc<Notify>,
+}
+
+impl SendQueue {
+    pub fn new(
+        database_connection: &Pool<ConnectionManager<PgConnection>>,
+        tenant_name: &str,
+    ) -> Self {
+        let (changes_tx, _) = tokio::sync::broadcast::channel(100);
+        SendQueue {
+            database_connection: database_connection.clone(),
+            tenant_name: tenant_name.to_string(),
+            changes_tx,
+            queued: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Returns the statuses of the messages queued with the idempotency key, one for every livechat
+    pub fn statuses(&self, key: &str) -> Result<Vec<SendStatus>, Box<dyn std::error::Error>> {
+        use schema::outbound_messages::dsl::*;
+        let queued_messages = outbound_messages
+            .filter(tenant.eq(&self.tenant_name))
+            .filter(idempotency_key.eq(key))
+            .order((livechat_id, part))
+            .load::<OutboundMessage>(&self.database_connection.get()?)?;
+        Ok(queued_messages.iter().map(to_send_status).collect())
+    }
+
+    /// Queues the parts of the message for every livechat and returns the statuses of everything queued with the idempotency key.
+    /// Livechats the key was already queued for are left alone.
+    pub fn enqueue(
+        &self,
+        key: &str,
+        livechat_ids: &[String],
+        message_parts: &[String],
+        message_priority: MessagePriority,
+        message_sender: AccountId,
+    ) -> Result<Vec<SendStatus>, Box<dyn std::error::Error>> {
+        let now = Utc::now().naive_utc();
+        let mut insert_messages = Vec::new();
+        for livechat_id in livechat_ids {
+            for (message_part, message_text) in message_parts.iter().enumerate() {
+                insert_messages.push(InsertOutboundMessage {
+                    tenant: self.tenant_name.clone(),
+                    idempotency_key: key.to_string(),
+                    livechat_id: livechat_id.clone(),
+                    message: message_text.clone(),
+                    part: message_part as i32,
+                    priority: priority_name(message_priority).to_string(),
+                    state: send_state_name(SendState::SendPending).to_string(),
+                    attempts: 0,
+                    next_attempt_at: now,
+                    queued_at: now,
+                    updated_at: now,
+                    sender: message_sender.to_string(),
+                });
+            }
+        }
+        let queued_messages: Vec<OutboundMessage> =
+            diesel::insert_into(schema::outbound_messages::table)
+                .values(&insert_messages)
+                .on_conflict_do_nothing()
+                .get_results(&self.database_connection.get()?)?;
+        for queued_message in &queued_messages {
+            // Nobody listening is not an error
+            let _ = self.changes_tx.send(to_send_status(queued_message));
+        }
+        if !queued_messages.is_empty() {
+            self.queued.notify_one();
+        }
+        self.statuses(key)
+    }
+
+    pub fn subscribe(&self) -> Receiver<SendStatus> {
+        self.changes_tx.subscribe()
+    }
+
+    /// Returns the messages that were neither sent nor given up on
+    fn pending(&self) -> Result<Vec<OutboundMessage>, Box<dyn std::error::Error>> {
+        use schema::outbound_messages::dsl::*;
+        let pending_messages = outbound_messages
+            .filter(tenant.eq(&self.tenant_name))
+            .filter(state.eq(send_state_name(SendState::SendPending)))
+            .load::<OutboundMessage>(&self.database_connection.get()?)?;
+        Ok(pending_messages)
+    }
+
+    /// Stores the outcome of an attempt to send the message and notifies everyone who subscribed
+    fn update(
+        &self,
+        queued_message_id: i32,
+        new_state: SendState,
+        new_attempts: i32,
+        new_error: Option<String>,
+        new_next_attempt_at: NaiveDateTime,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        use schema::outbound_messages::dsl::*;
+        let updated_message: OutboundMessage =
+            diesel::update(outbound_messages.filter(outbound_message_id.eq(queued_message_id)))
+                .set((
+                    state.eq(send_state_name(new_state)),
+                    attempts.eq(new_attempts),
+                    error.eq(new_error),
+                    next_attempt_at.eq(new_next_attempt_at),
+                    updated_at.eq(Utc::now().naive_utc()),
+                ))
+                .get_result(&self.database_connection.get()?)?;
+        let _ = self.changes_tx.send(to_send_status(&updated_message));
+        Ok(())
+    }
+
+    /// Gives up on the parts of a split message that come after the failed part, they would arrive out of order
+    fn fail_later_parts(
+        &self,
+        failed_message: &OutboundMessage,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        use schema::outbound_messages::dsl::*;
+        let failed_messages: Vec<OutboundMessage> = diesel::update(
+            outbound_messages
+                .filter(tenant.eq(&self.tenant_name))
+                .filter(idempotency_key.eq(&failed_message.idempotency_key))
+                .filter(livechat_id.eq(&failed_message.livechat_id))
+                .filter(part.gt(failed_message.part))
+                .filter(state.eq(send_state_name(SendState::SendPending))),
+        )
+        .set((
+            state.eq(send_state_name(SendState::SendFailed)),
+            error.eq(Some(format!(
+                "Part {} of the message could not be sent",
+                failed_message.part + 1
+            ))),
+            updated_at.eq(Utc::now().naive_utc()),
+        ))
+        .get_results(&self.database_connection.get()?)?;
+        for failed_message in &failed_messages {
+            let _ = self.changes_tx.send(to_send_status(failed_message));
+        }
+        Ok(())
+    }
+
+    /// Records that YouTube accepted the message and stores it with the chat messages right away,
+    /// flagged as sent by this service, so it is recognized when it comes back through the chat.
+    /// The message is marked as sent first, so it isn't sent again if storing the chat message fails.
+    fn mark_sent(
+        &self,
+        queued_message: &OutboundMessage,
+        new_attempts: i32,
+        sent_message: LiveChatMessage,
+        broadcast_id: Option<i32>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let now = Utc::now().naive_utc();
+        let snippet = sent_message.snippet.unwrap_or_default();
+        let sent_message_id = sent_message.id.unwrap_or_default();
+        let sent_at = parse_youtube_time(&snippet.published_at).unwrap_or(now);
+        let chat_message = YouTubeChatMessage {
+            channel_id: snippet.author_channel_id.unwrap_or_default(),
+            message: queued_message.message.clone(),
+            sent_at_timestamp: Some(to_timestamp(DateTime::from_utc(sent_at, Utc))),
+            received_at_timestamp: Some(to_timestamp(DateTime::from_utc(now, Utc))),
+            message_id: sent_message_id.clone(),
+            message_type: YouTubeChatMessageType::TextMessage as i32,
+            livechat_id: queued_message.livechat_id.clone(),
+            sent_by_service: true,
+            ..Default::default()
+        };
+        use schema::outbound_messages::dsl::*;
+        let updated_message: OutboundMessage = diesel::update(
+            outbound_messages.filter(outbound_message_id.eq(queued_message.outbound_message_id)),
+        )
+        .set((
+            state.eq(send_state_name(SendState::SendSent)),
+            attempts.eq(new_attempts),
+            error.eq(None::<String>),
+            youtube_message_id.eq(Some(sent_message_id)),
+            published_at.eq(Some(sent_at)),
+            updated_at.eq(now),
+        ))
+        .get_result(&self.database_connection.get()?)?;
+        let _ = self.changes_tx.send(to_send_status(&updated_message));
+
+        insert_own_message(
+            &self.database_connection,
+            &self.tenant_name,
+            &chat_message,
+            broadcast_id,
+        )
+    }
+}
+
+/// Limits how fast messages are sent: up to `capacity` at once, then `per_second` on average
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    per_second: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// Reads the rate limit of the tenant from the environment.
+    /// YTS_SEND_RATE is the number of messages per minute, 30 by default, YTS_SEND_BURST how many may be sent at once, 3 by default.
+    fn from_env(tenant: &str) -> Self {
+        let per_minute: u32 = tenant_env_var(tenant, "SEND_RATE")
+            .and_then(|rate| rate.parse().ok())
+            .unwrap_or(30);
+        let burst: u32 = tenant_env_var(tenant, "SEND_BURST")
+            .and_then(|burst| burst.parse().ok())
+            .unwrap_or(3);
+        let capacity = burst.max(1) as f64;
+        TokenBucket {
+            capacity,
+            tokens: capacity,
+            per_second: per_minute.max(1) as f64 / 60.0,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Waits until a message may be sent
+    async fn take(&mut self) {
+        loop {
+            let now = Instant::now();
+            let refill = now.duration_since(self.refilled_at).as_secs_f64() * self.per_second;
+            self.tokens = (self.tokens + refill).min(self.capacity);
+            self.refilled_at = now;
+            if self.tokens >= 1.0 {
+                self.tokens -= 1.0;
+                return;
+            }
+            let wait_secs = (1.0 - self.tokens) / self.per_second;
+            tokio::time::sleep(Duration::from_secs_f64(wait_secs)).await;
+        }
+    }
+}
+
+/// Sends the queued message as the bot or the streamer, whoever it was queued for, and records the outcome.
+/// Errors that may go away are retried with the backoff of the polling policy until the attempts are used up.
+async fn send_queued_message(tenant: &Tenant, queued_message: &OutboundMessage, max_attempts: i32) {
+    // Build a livechat message
+    let mut livechat_message = LiveChatMessage::default();
+    let mut livechat_snippet = LiveChatMessageSnippet::default();
+    let mut text_message_details = LiveChatTextMessageDetails::default();
+    livechat_snippet.type_ = Some("textMessageEvent".to_string());
+    livechat_snippet.live_chat_id = Some(queued_message.livechat_id.clone());
+    text_message_details.message_text = Some(queued_message.message.clone());
+    livechat_snippet.text_message_details = Some(text_message_details);
+    livechat_message.snippet = Some(livechat_snippet);
+
+    // Send the message to the YouTube API
+    let sender = AccountId::from_name(&queued_message.sender).unwrap_or(AccountId::BOT);
+    let hub = if sender == AccountId::STREAMER {
+        &tenant.streamer_hub
+    } else {
+        &tenant.bot_hub
+    };
+    tenant.record_call(sender, "liveChatMessages.insert").await;
+    let response_result = hub
+        .live_chat_messages()
+        .insert(livechat_message)
+        .add_part("snippet")
+        .doit()
+        .await;
+    let attempts = queued_message.attempts + 1;
+    let now = Utc::now().naive_utc();
+    let update_result = match response_result {
+        Ok((_, sent_message)) => {
+            debug!(
+                "Sent message {} to livechat {} as the {}",
+                queued_message.idempotency_key, queued_message.livechat_id, sender
+            );
+            let broadcast_id = tenant
+                .livechats
+                .read()
+                .await
+                .get(&queued_message.livechat_id)
+                .and_then(|livechat| livechat.broadcast_id);
+            tenant
+                .send_queue
+                .mark_sent(queued_message, attempts, sent_message, broadcast_id)
+        }
+        Err(e) => {
+            let error_class = classify_google_error(&e);
+            let error_message = log_google_errors(e).await;
+            let retry_delay = if attempts < max_attempts {
+                tenant
+                    .polling_policy
+                    .backoff_delay(error_class, attempts as u32)
+            } else {
+                None
+            };
+            match retry_delay {
+                Some(delay) => {
+                    warn!(
+                        "Sending message {} to livechat {} failed with a {:?} error, retrying in {}ms",
+                        queued_message.idempotency_key,
+                        queued_message.livechat_id,
+                        error_class,
+                        delay.as_millis()
+                    );
+                    let next_attempt_at = now
+                        + chrono::Duration::from_std(delay)
+                            .unwrap_or_else(|_| chrono::Duration::zero());
+                    tenant.send_queue.update(
+                        queued_message.outbound_message_id,
+                        SendState::SendPending,
+                        attempts,
+                        Some(error_message),
+                        next_attempt_at,
+                    )
+                }
+                None => {
+                    error!(
+                        "Giving up on sending message {} to livechat {} after {} attempts",
+                        queued_message.idempotency_key, queued_message.livechat_id, attempts
+                    );
+                    tenant
+                        .send_queue
+                        .update(
+                            queued_message.outbound_message_id,
+                            SendState::SendFailed,
+                            attempts,
+                            Some(error_message),
+                            now,
+                        )
+                        .and_then(|_| tenant.send_queue.fail_later_parts(queued_message))
+                }
+            }
+        }
+    };
+    if let Err(e) = update_result {
+        error!(
+            "Unable to update the status of message {}: {}",
+            queued_message.idempotency_key, e
+        );
+    }
+}
+
+/// Sends the queued messages of the tenant one by one, high priority first, as fast as the rate limit allows.
+/// YTS_SEND_MAX_ATTEMPTS is how often a message is tried before it fails, 5 by default.
+pub async fn deliver_messages(tenant: &Tenant) {
+    let send_queue = &tenant.send_queue;
+    let max_attempts: i32 = tenant_env_var(&tenant.name, "SEND_MAX_ATTEMPTS")
+        .and_then(|attempts| attempts.parse().ok())
+        .unwrap_or(5);
+    let mut token_bucket = TokenBucket::from_env(&tenant.name);
+    loop {
+        let pending_messages = match send_queue.pending() {
+            Ok(pending_messages) => pending_messages,
+            Err(e) => {
+                error!("Unable to read the queued messages: {}", e);
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                continue;
+            }
+        };
+        // Parts of a split message wait for the earlier parts, so they arrive in order
+        let unblocked_messages: Vec<&OutboundMessage> = pending_messages
+            .iter()
+            .filter(|pending_message| {
+                !pending_messages.iter().any(|earlier_message| {
+                    earlier_message.idempotency_key == pending_message.idempotency_key
+                        && earlier_message.livechat_id == pending_message.livechat_id
+                        && earlier_message.part < pending_message.part
+                })
+            })
+            .collect();
+        // Pick the oldest message of the highest priority whose retry is due
+        let now = Utc::now().naive_utc();
+        let next_message = unblocked_messages
+            .iter()
+            .filter(|pending_message| pending_message.next_attempt_at <= now)
+            .min_by_key(|pending_message| {
+                (
+                    Reverse(priority_rank(priority_from_name(&pending_message.priority))),
+                    pending_message.outbound_message_id,
+                )
+            });
+        let next_message = match next_message {
+            Some(next_message) => (*next_message).clone(),
+            None => {
+                // Wait for the next retry or a new message, whatever comes first.
+                // Blocked parts are left out, their turn only comes once the earlier parts were sent.
+                let wait_for = unblocked_messages
+                    .iter()
+                    .map(|pending_message| pending_message.next_attempt_at)
+                    .min()
+                    .map(|next_attempt_at| (next_attempt_at - now).to_std().unwrap_or_default())
+                    .unwrap_or_else(|| Duration::from_secs(3600));
+                tokio::select! {
+                    _ = tokio::time::sleep(wait_for) => {}
+                    _ = send_queue.queued.notified() => {}
+                }
+                continue;
+            }
+        };
+        token_bucket.take().await;
+        send_queued_message(tenant, &next_message, max_attempts).await;
+    }
+}
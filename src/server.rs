@@ -3,34 +3,58 @@ extern crate diesel;
 #[macro_use]
 extern crate diesel_migrations;
 
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
-use ::log::{debug, error, info};
+use ::log::{debug, error, info, warn};
+use chrono::NaiveDateTime;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel::r2d2::ConnectionManager;
-use google_youtube3::api::{LiveChatMessage, LiveChatMessageSnippet, LiveChatTextMessageDetails};
-use google_youtube3::YouTube;
-use models::InsertLivechatMessage;
+use futures_util::future::join_all;
+use google_youtube3::api::{
+    ChannelProfileDetails, LiveChatBan, LiveChatBanSnippet, LiveChatMessage,
+    LiveChatMessageSnippet, LiveChatModerator, LiveChatModeratorSnippet,
+};
+use models::{
+    InsertBroadcast, InsertLivechatBan, InsertLivechatMembershipEvent, InsertLivechatMessage,
+    InsertLivechatSuperChat,
+};
 use prost_types::Timestamp;
 use r2d2::Pool;
-use tokio::sync::broadcast::Sender;
-use tokio::sync::mpsc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, RwLock};
+use tokio::task::JoinHandle;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{transport::Server, Response, Status};
 
+mod auth;
+mod broadcast;
+mod cursor;
 mod log;
+mod message_text;
+mod metrics;
 mod models;
+mod permissions;
+mod polling;
+mod quota;
 mod schema;
+mod selection;
+mod send_queue;
+mod tenant;
+mod timers;
+mod token_health;
+mod token_storage;
 mod youtube;
 
 embed_migrations!();
 
 pub mod youtube_service {
     use crate::models::LivechatMessage;
+    use google_youtube3::api::LiveChatModerator;
     use prost_types::Timestamp;
 
     tonic::include_proto!("youtubeservice");
@@ -52,6 +76,20 @@ pub mod youtube_service {
                 sent_at_timestamp: Some(sent_at_timestamp),
                 received_at_timestamp: Some(received_at_timestamp),
                 message_id: msg.youtube_id,
+                message_type: YouTubeChatMessageType::TextMessage as i32,
+                super_chat_details: None,
+                membership_details: None,
+                is_chat_owner: msg.is_chat_owner,
+                is_chat_moderator: msg.is_chat_moderator,
+                is_chat_sponsor: msg.is_chat_sponsor,
+                is_verified: msg.is_verified,
+                profile_image_url: msg.profile_image_url.unwrap_or_default(),
+                deleted_at_timestamp: msg.deleted_at.map(|deleted_at| Timestamp {
+                    seconds: deleted_at.timestamp() as i64,
+                    nanos: deleted_at.timestamp_subsec_nanos() as i32,
+                }),
+                livechat_id: msg.livechat_id.unwrap_or_default(),
+                sent_by_service: msg.sent_by_service,
             }
         }
     }
@@ -73,6 +111,53 @@ pub mod youtube_service {
                 sent_at_timestamp: Some(sent_at_timestamp),
                 received_at_timestamp: Some(received_at_timestamp),
                 message_id: msg.youtube_id.clone(),
+                message_type: YouTubeChatMessageType::TextMessage as i32,
+                super_chat_details: None,
+                membership_details: None,
+                is_chat_owner: msg.is_chat_owner,
+                is_chat_moderator: msg.is_chat_moderator,
+                is_chat_sponsor: msg.is_chat_sponsor,
+                is_verified: msg.is_verified,
+                profile_image_url: msg.profile_image_url.clone().unwrap_or_default(),
+                deleted_at_timestamp: msg.deleted_at.map(|deleted_at| Timestamp {
+                    seconds: deleted_at.timestamp() as i64,
+                    nanos: deleted_at.timestamp_subsec_nanos() as i32,
+                }),
+                livechat_id: msg.livechat_id.clone().unwrap_or_default(),
+                sent_by_service: msg.sent_by_service,
+            }
+        }
+    }
+
+    impl ChatEvent {
+        /// Returns the chat message carried by this event, if it is one of the message-like events.
+        pub fn into_chat_message(self) -> Option<YouTubeChatMessage> {
+            match self.event {
+                Some(chat_event::Event::TextMessage(message))
+                | Some(chat_event::Event::SuperChat(message))
+                | Some(chat_event::Event::SuperSticker(message))
+                | Some(chat_event::Event::Membership(message)) => Some(message),
+                _ => None,
+            }
+        }
+
+        /// Whether the event is a message posted through SendMessage or QueueMessage of this service
+        pub fn is_own_message(&self) -> bool {
+            matches!(&self.event, Some(chat_event::Event::TextMessage(message)) if message.sent_by_service)
+        }
+    }
+
+    impl From<LiveChatModerator> for Moderator {
+        fn from(moderator: LiveChatModerator) -> Self {
+            let moderator_details = moderator
+                .snippet
+                .and_then(|snippet| snippet.moderator_details)
+                .unwrap_or_default();
+            Moderator {
+                moderator_id: moderator.id.unwrap_or_default(),
+                channel_id: moderator_details.channel_id.unwrap_or_default(),
+                display_name: moderator_details.display_name.unwrap_or_default(),
+                profile_image_url: moderator_details.profile_image_url.unwrap_or_default(),
             }
         }
     }
@@ -85,123 +170,1018 @@ pub mod youtube_service {
 }
 
 use youtube_service::you_tube_service_server::{YouTubeService, YouTubeServiceServer};
-use youtube_service::YouTubeChatMessage;
+use youtube_service::{
+    chat_event, AccountTokenStatus, AuthAccount, AuthChallenge, AuthState, BanType,
+    BroadcastSelection, BroadcastSelectionPolicy, BroadcastStateChange, BroadcastStatus,
+    ChatEndedEvent, ChatEvent, ChatMode, ChatModeChangedEvent, IngestedChat, IngestionState,
+    MessageDeletedEvent, MessagePriority, SendState, SendStatus, Timer, TokenHealth,
+    UserBannedEvent, YouTubeChatMessage, YouTubeChatMessageType, YouTubeMembershipDetails,
+    YouTubeSuperChatDetails,
+};
 
-use crate::log::{log_google_errors, setup_log};
+use crate::auth::{AccountId, AuthChallenges};
+use crate::broadcast::watch_broadcasts;
+use crate::cursor::{delete_cursor, load_cursor, store_cursor};
+use crate::log::{google_error_to_status, log_google_errors, setup_log};
+use crate::message_text::{message_length, split_message, MAX_MESSAGE_LENGTH};
+use crate::metrics::serve_metrics;
 use crate::models::LivechatMessage;
-use crate::youtube::{authenticate_google, body_to_string, get_livechat_id};
+use crate::permissions::{admin_clients_from_env, AdminClients};
+use crate::polling::{classify_google_error, jitter, ErrorClass, PollingScheduler};
+use crate::quota::{quota_tracker_from_env, QuotaTracker};
+use crate::selection::{select_broadcasts, validate_broadcast_selection};
+use crate::send_queue::{deliver_messages, generate_idempotency_key};
+use crate::tenant::{
+    find_tenant, find_tenant_name, tenant_env_var, tenant_names_from_env, Tenant, Tenants,
+};
+use crate::timers::{run_timers, validate_timer};
+use crate::token_health::watch_tokens;
+use crate::token_storage::{token_storage_from_env, TokenStorage};
+use crate::youtube::{body_to_string, fetch_gifting_details, GiftingSnippet};
+
+/// A livechat the service is attached to
+#[derive(Clone, Debug, Default)]
+pub struct Livechat {
+    pub livechat_id: String,
+    /// The database id of the broadcast the chat belongs to, if known
+    pub broadcast_id: Option<i32>,
+    /// The YouTube ID of the broadcast the chat belongs to, if known
+    pub broadcast_youtube_id: Option<String>,
+}
+
+/// The livechats messages are currently fetched from keyed by their livechat id, empty while waiting for a broadcast
+pub type IngestedLivechats = Arc<RwLock<HashMap<String, Livechat>>>;
 
 pub struct YouTubeServiceImpl {
-    messages_tx: Sender<YouTubeChatMessage>,
-    youtube_hub: Arc<YouTube>,
-    livechat_id: String,
+    tenant_names: Vec<String>,
+    tenants: Tenants,
+    auth_challenges: AuthChallenges,
+    admin_clients: AdminClients,
+    quota: QuotaTracker,
     database_connection: Pool<ConnectionManager<PgConnection>>,
 }
 
 impl YouTubeServiceImpl {
     pub fn new(
-        tx: Sender<YouTubeChatMessage>,
-        youtube_hub: Arc<YouTube>,
-        livechat_id: String,
+        tenant_names: Vec<String>,
+        tenants: Tenants,
+        auth_challenges: AuthChallenges,
+        admin_clients: AdminClients,
+        quota: QuotaTracker,
         database_connection: Pool<ConnectionManager<PgConnection>>,
     ) -> Self {
         YouTubeServiceImpl {
-            messages_tx: tx,
-            youtube_hub,
-            livechat_id,
+            tenant_names,
+            tenants,
+            auth_challenges,
+            admin_clients,
+            quota,
             database_connection,
         }
     }
-}
 
-#[tonic::async_trait]
-impl YouTubeService for YouTubeServiceImpl {
-    async fn send_message(
+    /// Returns the tenant the request is scoped to, as long as its accounts are authenticated
+    async fn tenant<T>(&self, request: &tonic::Request<T>) -> Result<Tenant, Status> {
+        find_tenant(&self.tenant_names, &self.tenants, request).await
+    }
+
+    /// Streams the chat messages of the tenant, only those of the livechat if one is given
+    fn stream_chat_messages(
+        tenant: &Tenant,
+        livechat_id: String,
+        exclude_own_messages: bool,
+    ) -> ReceiverStream<Result<YouTubeChatMessage, Status>> {
+        // Create a pair of mpsc channels to send messages to the client
+        let (tx, rx) = mpsc::channel(4);
+        // Create a receiver for the broadcast stream because we have a new listener
+        let mut event_rx = tenant.events_tx.subscribe();
+
+        // Spawn a future that will forward the messages from the broadcast channel to the mpsc channel
+        tokio::spawn(async move {
+            while let Ok(event) = event_rx.recv().await {
+                if !livechat_id.is_empty() && event.livechat_id != livechat_id {
+                    continue;
+                }
+                // Only chat messages are sent here, everything else is only available through subscribe_events
+                let message = match event.into_chat_message() {
+                    Some(message) => message,
+                    None => continue,
+                };
+                if exclude_own_messages && message.sent_by_service {
+                    continue;
+                }
+                if tx.is_closed() {
+                    debug!("Someone closed the channel. Good bye!");
+                    break;
+                }
+
+                if let Err(e) = tx.send(Ok(message)).await {
+                    error!("Error sending message: {}", e);
+                }
+            }
+        });
+
+        ReceiverStream::new(rx)
+    }
+
+    /// Streams the events of the tenant, only those of the livechat if one is given
+    fn stream_chat_events(
+        tenant: &Tenant,
+        livechat_id: String,
+        exclude_own_messages: bool,
+    ) -> ReceiverStream<Result<ChatEvent, Status>> {
+        // Create a pair of mpsc channels to send events to the client
+        let (tx, rx) = mpsc::channel(4);
+        // Create a receiver for the broadcast stream because we have a new listener
+        let mut event_rx = tenant.events_tx.subscribe();
+
+        // Spawn a future that will forward the events from the broadcast channel to the mpsc channel
+        tokio::spawn(async move {
+            while let Ok(event) = event_rx.recv().await {
+                if !livechat_id.is_empty() && event.livechat_id != livechat_id {
+                    continue;
+                }
+                if exclude_own_messages && event.is_own_message() {
+                    continue;
+                }
+                if tx.is_closed() {
+                    debug!("Someone closed the channel. Good bye!");
+                    break;
+                }
+
+                if let Err(e) = tx.send(Ok(event)).await {
+                    error!("Error sending event: {}", e);
+                }
+            }
+        });
+
+        ReceiverStream::new(rx)
+    }
+
+    /// Returns the moderators of the livechat, the single followed chat if no livechat id is given
+    async fn moderators(
+        tenant: &Tenant,
+        livechat_id: &str,
+    ) -> Result<youtube_service::Moderators, Status> {
+        let livechat_id = tenant.target_livechat_id(livechat_id).await?;
+        let mut moderators: Vec<youtube_service::Moderator> = Vec::new();
+        let mut page_token: Option<String> = None;
+        // Moderators can only be managed by the owner of the broadcast, so the streamer hub is used here
+        loop {
+            let mut prepare_moderators = tenant
+                .streamer_hub
+                .live_chat_moderators()
+                .list(livechat_id.as_str(), &vec!["snippet".to_string()])
+                .max_results(50);
+            // If we have a page token, add it to the query
+            if let Some(token) = page_token.as_ref() {
+                prepare_moderators = prepare_moderators.page_token(token.as_str());
+            }
+            tenant
+                .record_call(AccountId::STREAMER, "liveChatModerators.list")
+                .await;
+            let response_result = prepare_moderators.doit().await;
+            // If there was an error, log it and return the error to the client
+            let (_, response) = match response_result {
+                Ok(response) => response,
+                Err(e) => return Err(google_error_to_status(e).await),
+            };
+            if let Some(items) = response.items {
+                moderators.extend(items.into_iter().map(|m| m.into()));
+            }
+            page_token = response.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+        Ok(youtube_service::Moderators { moderators })
+    }
+
+    /// Queues the message of the request for every targeted livechat and returns its send statuses
+    async fn enqueue_message(
+        &self,
+        request: tonic::Request<youtube_service::SendMessageRequest>,
+    ) -> Result<youtube_service::SendStatuses, Status> {
+        let tenant = self.tenant(&request).await?;
+        // Readers only read chats, the bot and the streamer are up to the permissions of the client
+        let sender = match AuthAccount::from_i32(request.get_ref().sender) {
+            Some(AuthAccount::Bot) => AccountId::BOT,
+            Some(AuthAccount::Streamer) => AccountId::STREAMER,
+            _ => {
+                return Err(Status::new(
+                    tonic::Code::InvalidArgument,
+                    "Messages can only be sent as the bot or the streamer",
+                ))
+            }
+        };
+        tenant
+            .sender_permissions
+            .authorize(&request, sender.account)?;
+        let send_message_request = request.into_inner();
+        let idempotency_key = if send_message_request.idempotency_key.is_empty() {
+            generate_idempotency_key()
+        } else {
+            send_message_request.idempotency_key.clone()
+        };
+        // Subscribe before queueing, so no status change is missed while waiting for the delivery
+        let mut status_rx = tenant.send_queue.subscribe();
+        let statuses_result = tenant.send_queue.statuses(&idempotency_key);
+        let mut statuses = match statuses_result {
+            Ok(statuses) => statuses,
+            Err(e) => {
+                error!("Error while reading send statuses: {}", e);
+                return Err(Status::new(tonic::Code::Internal, e.to_string()));
+            }
+        };
+        // A retried request gets the statuses of the first one
+        if statuses.is_empty() {
+            // Low priority messages are the first thing to go when the quota runs low
+            if send_message_request.priority == MessagePriority::PriorityLow as i32
+                && tenant.quota.is_throttled().await
+            {
+                return Err(Status::new(
+                    tonic::Code::ResourceExhausted,
+                    "The quota threshold is reached, low priority messages are rejected",
+                ));
+            }
+            // YouTube rejects long messages, so they are either split or rejected right away
+            let message = send_message_request.message.trim();
+            let length = message_length(message);
+            let message_parts = if length == 0 {
+                return Err(Status::new(
+                    tonic::Code::InvalidArgument,
+                    "The message is empty",
+                ));
+            } else if length <= MAX_MESSAGE_LENGTH {
+                vec![message.to_string()]
+            } else if send_message_request.split_long_messages {
+                split_message(message)
+            } else {
+                return Err(Status::new(
+                    tonic::Code::InvalidArgument,
+                    format!(
+                        "The message is {} characters long, YouTube allows {}. Set split_long_messages to send it in several parts.",
+                        length, MAX_MESSAGE_LENGTH
+                    ),
+                ));
+            };
+            let livechat_ids = if send_message_request.all_livechats {
+                tenant.target_livechat_ids("").await?
+            } else {
+                vec![
+                    tenant
+                        .target_livechat_id(&send_message_request.livechat_id)
+                        .await?,
+                ]
+            };
+            let priority = MessagePriority::from_i32(send_message_request.priority)
+                .unwrap_or(MessagePriority::PriorityNormal);
+            // Queue the message, it is sent as soon as the rate limit allows
+            let enqueue_result = tenant.send_queue.enqueue(
+                &idempotency_key,
+                &livechat_ids,
+                &message_parts,
+                priority,
+                sender,
+            );
+            statuses = match enqueue_result {
+                Ok(statuses) => statuses,
+                Err(e) => {
+                    error!("Error while queueing message: {}", e);
+                    return Err(Status::new(tonic::Code::Internal, e.to_string()));
+                }
+            };
+        }
+        // Wait until the message was sent to every livechat or given up on
+        if send_message_request.wait_for_delivery {
+            while statuses
+                .iter()
+                .any(|status| status.state == SendState::SendPending as i32)
+            {
+                match status_rx.recv().await {
+                    Ok(status) if status.idempotency_key != idempotency_key => continue,
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                }
+                statuses = match tenant.send_queue.statuses(&idempotency_key) {
+                    Ok(statuses) => statuses,
+                    Err(e) => {
+                        error!("Error while reading send statuses: {}", e);
+                        return Err(Status::new(tonic::Code::Internal, e.to_string()));
+                    }
+                };
+            }
+        }
+        Ok(youtube_service::SendStatuses { statuses })
+    }
+}
+
+#[tonic::async_trait]
+impl YouTubeService for YouTubeServiceImpl {
+    async fn send_message(
+        &self,
+        request: tonic::Request<youtube_service::SendMessageRequest>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        self.enqueue_message(request).await?;
+        return Ok(Response::new(()));
+    }
+
+    async fn queue_message(
+        &self,
+        request: tonic::Request<youtube_service::SendMessageRequest>,
+    ) -> Result<tonic::Response<youtube_service::SendStatuses>, tonic::Status> {
+        let statuses = self.enqueue_message(request).await?;
+        return Ok(Response::new(statuses));
+    }
+
+    type SubscribeMessagesStream = ReceiverStream<Result<YouTubeChatMessage, Status>>;
+
+    async fn subscribe_messages(
+        &self,
+        request: tonic::Request<()>,
+    ) -> Result<tonic::Response<Self::SubscribeMessagesStream>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let stream = Self::stream_chat_messages(&tenant, String::new(), false);
+        return Ok(Response::new(stream));
+    }
+
+    type SubscribeLivechatMessagesStream = ReceiverStream<Result<YouTubeChatMessage, Status>>;
+
+    async fn subscribe_livechat_messages(
+        &self,
+        request: tonic::Request<youtube_service::SubscribeRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeLivechatMessagesStream>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let subscribe_request = request.into_inner();
+        let stream = Self::stream_chat_messages(
+            &tenant,
+            subscribe_request.livechat_id,
+            subscribe_request.exclude_own_messages,
+        );
+        return Ok(Response::new(stream));
+    }
+
+    type SubscribeEventsStream = ReceiverStream<Result<ChatEvent, Status>>;
+
+    async fn subscribe_events(
+        &self,
+        request: tonic::Request<()>,
+    ) -> Result<tonic::Response<Self::SubscribeEventsStream>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let stream = Self::stream_chat_events(&tenant, String::new(), false);
+        return Ok(Response::new(stream));
+    }
+
+    type SubscribeLivechatEventsStream = ReceiverStream<Result<ChatEvent, Status>>;
+
+    async fn subscribe_livechat_events(
+        &self,
+        request: tonic::Request<youtube_service::SubscribeRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeLivechatEventsStream>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let subscribe_request = request.into_inner();
+        let stream = Self::stream_chat_events(
+            &tenant,
+            subscribe_request.livechat_id,
+            subscribe_request.exclude_own_messages,
+        );
+        return Ok(Response::new(stream));
+    }
+
+    async fn get_messages(
+        &self,
+        request: tonic::Request<youtube_service::GetMessageRequest>,
+    ) -> Result<tonic::Response<youtube_service::YouTubeChatMessages>, tonic::Status> {
+        // Stored messages can be read before the accounts of the tenant are authenticated
+        let tenant_name = find_tenant_name(&self.tenant_names, &request)?;
+        let get_message_request = request.into_inner();
+        use crate::schema::livechat_messages::dsl::*;
+
+        // Get messages from the database, leaving out deleted messages unless requested otherwise
+        let db_conn = &self.database_connection.get().unwrap();
+        let mut query = livechat_messages
+            .filter(tenant.eq(&tenant_name))
+            .into_boxed();
+        if !get_message_request.include_deleted {
+            query = query.filter(deleted_at.is_null());
+        }
+        if !get_message_request.broadcast_id.is_empty() {
+            use crate::schema::broadcasts::dsl as broadcasts_dsl;
+            let broadcast_row_id = broadcasts_dsl::broadcasts
+                .filter(broadcasts_dsl::youtube_id.eq(&get_message_request.broadcast_id))
+                .filter(broadcasts_dsl::tenant.eq(&tenant_name))
+                .select(broadcasts_dsl::broadcast_id)
+                .first::<i32>(db_conn)
+                .optional()
+                .unwrap();
+            match broadcast_row_id {
+                Some(row_id) => query = query.filter(broadcast_id.eq(row_id)),
+                None => {
+                    return Err(Status::new(
+                        tonic::Code::NotFound,
+                        format!("Unknown broadcast {}", get_message_request.broadcast_id),
+                    ))
+                }
+            }
+        }
+        if !get_message_request.livechat_id.is_empty() {
+            query = query.filter(livechat_id.eq(&get_message_request.livechat_id));
+        }
+        let results = query
+            .order(sent_at.desc())
+            .limit(get_message_request.limit.into())
+            .offset(get_message_request.offset.into())
+            .load::<LivechatMessage>(db_conn)
+            .unwrap();
+        let converted: Vec<YouTubeChatMessage> = results.iter().map(|m| m.into()).collect();
+        return Ok(Response::new(converted.into()));
+    }
+
+    async fn delete_message(
+        &self,
+        request: tonic::Request<youtube_service::DeleteMessageRequest>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let delete_message_request = request.into_inner();
+
+        // Delete the message through the YouTube API
+        tenant
+            .record_call(AccountId::BOT, "liveChatMessages.delete")
+            .await;
+        let response_result = tenant
+            .bot_hub
+            .live_chat_messages()
+            .delete(delete_message_request.message_id.as_str())
+            .doit()
+            .await;
+        // If there was an error, log it and return the error to the client
+        if let Err(e) = response_result {
+            return Err(google_error_to_status(e).await);
+        }
+        info!("Deleted message {}", delete_message_request.message_id);
+        return Ok(Response::new(()));
+    }
+
+    async fn ban_user(
+        &self,
+        request: tonic::Request<youtube_service::BanUserRequest>,
+    ) -> Result<tonic::Response<youtube_service::BanUserResponse>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let ban_user_request = request.into_inner();
+        let temporary = ban_user_request.ban_type == BanType::Temporary as i32;
+        if temporary && ban_user_request.ban_duration_seconds == 0 {
+            return Err(Status::new(
+                tonic::Code::InvalidArgument,
+                "Temporary bans require a ban duration",
+            ));
+        }
+
+        // Build a livechat ban
+        let livechat_id = tenant
+            .target_livechat_id(&ban_user_request.livechat_id)
+            .await?;
+        let mut livechat_ban = LiveChatBan::default();
+        let mut livechat_ban_snippet = LiveChatBanSnippet::default();
+        let mut banned_user_details = ChannelProfileDetails::default();
+        banned_user_details.channel_id = Some(ban_user_request.channel_id.clone());
+        livechat_ban_snippet.live_chat_id = Some(livechat_id);
+        livechat_ban_snippet.banned_user_details = Some(banned_user_details);
+        if temporary {
+            livechat_ban_snippet.type_ = Some("temporary".to_string());
+            livechat_ban_snippet.ban_duration_seconds =
+                Some(ban_user_request.ban_duration_seconds.to_string());
+        } else {
+            livechat_ban_snippet.type_ = Some("permanent".to_string());
+        }
+        livechat_ban.snippet = Some(livechat_ban_snippet);
+
+        // Send the ban to the YouTube API
+        tenant
+            .record_call(AccountId::BOT, "liveChatBans.insert")
+            .await;
+        let response_result = tenant
+            .bot_hub
+            .live_chat_bans()
+            .insert(livechat_ban)
+            .add_part("snippet")
+            .doit()
+            .await;
+        // If there was an error, log it and return the error to the client
+        let (_, created_ban) = match response_result {
+            Ok(response) => response,
+            Err(e) => return Err(google_error_to_status(e).await),
+        };
+        info!("Banned {}", ban_user_request.channel_id);
+        return Ok(Response::new(youtube_service::BanUserResponse {
+            ban_id: created_ban.id.unwrap_or_default(),
+        }));
+    }
+
+    async fn unban_user(
+        &self,
+        request: tonic::Request<youtube_service::UnbanUserRequest>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let unban_user_request = request.into_inner();
+
+        // Lift the ban through the YouTube API
+        tenant
+            .record_call(AccountId::BOT, "liveChatBans.delete")
+            .await;
+        let response_result = tenant
+            .bot_hub
+            .live_chat_bans()
+            .delete(unban_user_request.ban_id.as_str())
+            .doit()
+            .await;
+        // If there was an error, log it and return the error to the client
+        if let Err(e) = response_result {
+            return Err(google_error_to_status(e).await);
+        }
+        info!("Lifted ban {}", unban_user_request.ban_id);
+        return Ok(Response::new(()));
+    }
+
+    async fn list_moderators(
+        &self,
+        request: tonic::Request<()>,
+    ) -> Result<tonic::Response<youtube_service::Moderators>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let moderators = Self::moderators(&tenant, "").await?;
+        return Ok(Response::new(moderators));
+    }
+
+    async fn list_livechat_moderators(
+        &self,
+        request: tonic::Request<youtube_service::ListModeratorsRequest>,
+    ) -> Result<tonic::Response<youtube_service::Moderators>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let moderators = Self::moderators(&tenant, &request.into_inner().livechat_id).await?;
+        return Ok(Response::new(moderators));
+    }
+
+    async fn add_moderator(
+        &self,
+        request: tonic::Request<youtube_service::AddModeratorRequest>,
+    ) -> Result<tonic::Response<youtube_service::Moderator>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let add_moderator_request = request.into_inner();
+
+        // Build a livechat moderator
+        let livechat_id = tenant
+            .target_livechat_id(&add_moderator_request.livechat_id)
+            .await?;
+        let mut livechat_moderator = LiveChatModerator::default();
+        let mut livechat_moderator_snippet = LiveChatModeratorSnippet::default();
+        let mut moderator_details = ChannelProfileDetails::default();
+        moderator_details.channel_id = Some(add_moderator_request.channel_id.clone());
+        livechat_moderator_snippet.live_chat_id = Some(livechat_id);
+        livechat_moderator_snippet.moderator_details = Some(moderator_details);
+        livechat_moderator.snippet = Some(livechat_moderator_snippet);
+
+        // Send the moderator to the YouTube API
+        tenant
+            .record_call(AccountId::STREAMER, "liveChatModerators.insert")
+            .await;
+        let response_result = tenant
+            .streamer_hub
+            .live_chat_moderators()
+            .insert(livechat_moderator)
+            .add_part("snippet")
+            .doit()
+            .await;
+        // If there was an error, log it and return the error to the client
+        let (_, created_moderator) = match response_result {
+            Ok(response) => response,
+            Err(e) => return Err(google_error_to_status(e).await),
+        };
+        info!("Added {} as moderator", add_moderator_request.channel_id);
+        return Ok(Response::new(created_moderator.into()));
+    }
+
+    async fn remove_moderator(
+        &self,
+        request: tonic::Request<youtube_service::RemoveModeratorRequest>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let remove_moderator_request = request.into_inner();
+
+        // Remove the moderator through the YouTube API
+        tenant
+            .record_call(AccountId::STREAMER, "liveChatModerators.delete")
+            .await;
+        let response_result = tenant
+            .streamer_hub
+            .live_chat_moderators()
+            .delete(remove_moderator_request.moderator_id.as_str())
+            .doit()
+            .await;
+        // If there was an error, log it and return the error to the client
+        if let Err(e) = response_result {
+            return Err(google_error_to_status(e).await);
+        }
+        info!(
+            "Removed moderator {}",
+            remove_moderator_request.moderator_id
+        );
+        return Ok(Response::new(()));
+    }
+
+    async fn get_status(
+        &self,
+        request: tonic::Request<()>,
+    ) -> Result<tonic::Response<youtube_service::ServiceStatus>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let mut chats: Vec<IngestedChat> = tenant
+            .livechats
+            .read()
+            .await
+            .values()
+            .map(|livechat| IngestedChat {
+                livechat_id: livechat.livechat_id.clone(),
+                broadcast_id: livechat.broadcast_youtube_id.clone().unwrap_or_default(),
+            })
+            .collect();
+        chats.sort_by(|a, b| a.livechat_id.cmp(&b.livechat_id));
+        // Clients from before several chats could be followed only know a single chat
+        let first_chat = chats.first().cloned().unwrap_or_default();
+        let ingestion_state = if chats.is_empty() {
+            IngestionState::WaitingForBroadcast
+        } else {
+            IngestionState::Ingesting
+        };
+        return Ok(Response::new(youtube_service::ServiceStatus {
+            ingestion_state: ingestion_state as i32,
+            livechat_id: first_chat.livechat_id,
+            broadcast_id: first_chat.broadcast_id,
+            chats,
+        }));
+    }
+
+    async fn get_broadcast_selection(
+        &self,
+        request: tonic::Request<()>,
+    ) -> Result<tonic::Response<BroadcastSelection>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let selection = tenant.broadcast_selection.get().await;
+        return Ok(Response::new(selection));
+    }
+
+    async fn set_broadcast_selection(
+        &self,
+        request: tonic::Request<BroadcastSelection>,
+    ) -> Result<tonic::Response<BroadcastSelection>, tonic::Status> {
+        // Changing what is followed is up to admins
+        self.admin_clients.authorize(&request)?;
+        let tenant = self.tenant(&request).await?;
+        let selection = request.into_inner();
+        if let Err(e) = validate_broadcast_selection(&selection) {
+            return Err(Status::new(tonic::Code::InvalidArgument, e));
+        }
+
+        info!(
+            "Changing broadcast selection of tenant {} to {:?}",
+            tenant.name, selection
+        );
+        // The ingestion loop picks up the new selection right away
+        tenant.broadcast_selection.set(selection.clone()).await;
+        return Ok(Response::new(selection));
+    }
+
+    type SubscribeBroadcastStateStream = ReceiverStream<Result<BroadcastStateChange, Status>>;
+
+    async fn subscribe_broadcast_state(
+        &self,
+        request: tonic::Request<()>,
+    ) -> Result<tonic::Response<Self::SubscribeBroadcastStateStream>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        // Create a pair of mpsc channels to send state changes to the client
+        let (tx, rx) = mpsc::channel(4);
+        // Create a receiver for the broadcast stream because we have a new listener
+        let mut state_rx = tenant.broadcast_state_tx.subscribe();
+        // The client first gets the last known state of every broadcast
+        let known_states: Vec<BroadcastStateChange> = tenant
+            .broadcast_states
+            .read()
+            .await
+            .values()
+            .cloned()
+            .collect();
+
+        // Spawn a future that will forward the state changes from the broadcast channel to the mpsc channel
+        tokio::spawn(async move {
+            for state in known_states {
+                if let Err(e) = tx.send(Ok(state)).await {
+                    error!("Error sending broadcast state: {}", e);
+                }
+            }
+            while let Ok(state) = state_rx.recv().await {
+                if tx.is_closed() {
+                    debug!("Someone closed the channel. Good bye!");
+                    break;
+                }
+
+                if let Err(e) = tx.send(Ok(state)).await {
+                    error!("Error sending broadcast state: {}", e);
+                }
+            }
+        });
+
+        // Return the channel that will receive the state changes
+        return Ok(Response::new(ReceiverStream::new(rx)));
+    }
+
+    async fn list_tenants(
+        &self,
+        _: tonic::Request<()>,
+    ) -> Result<tonic::Response<youtube_service::Tenants>, tonic::Status> {
+        let tenants = self.tenant_names.clone();
+        return Ok(Response::new(youtube_service::Tenants { tenants }));
+    }
+
+    async fn get_auth_status(
+        &self,
+        request: tonic::Request<()>,
+    ) -> Result<tonic::Response<youtube_service::AuthStatus>, tonic::Status> {
+        // The challenges carry the codes that authenticate the accounts, so only admins may see them
+        self.admin_clients.authorize(&request)?;
+        let ready = self.tenants.read().await.len() == self.tenant_names.len();
+        let challenges = self.auth_challenges.list().await;
+        return Ok(Response::new(youtube_service::AuthStatus {
+            ready,
+            challenges,
+        }));
+    }
+
+    type SubscribeAuthChallengesStream = ReceiverStream<Result<AuthChallenge, Status>>;
+
+    async fn subscribe_auth_challenges(
+        &self,
+        request: tonic::Request<()>,
+    ) -> Result<tonic::Response<Self::SubscribeAuthChallengesStream>, tonic::Status> {
+        self.admin_clients.authorize(&request)?;
+        // Create a pair of mpsc channels to send challenges to the client
+        let (tx, rx) = mpsc::channel(4);
+        // Create a receiver for the broadcast stream because we have a new listener
+        let mut challenges_rx = self.auth_challenges.subscribe();
+        // The client first gets the latest challenge of every account
+        let known_challenges = self.auth_challenges.list().await;
+
+        // Spawn a future that will forward the challenges from the broadcast channel to the mpsc channel
+        tokio::spawn(async move {
+            for challenge in known_challenges {
+                if let Err(e) = tx.send(Ok(challenge)).await {
+                    error!("Error sending auth challenge: {}", e);
+                }
+            }
+            while let Ok(challenge) = challenges_rx.recv().await {
+                if tx.is_closed() {
+                    debug!("Someone closed the channel. Good bye!");
+                    break;
+                }
+
+                if let Err(e) = tx.send(Ok(challenge)).await {
+                    error!("Error sending auth challenge: {}", e);
+                }
+            }
+        });
+
+        // Return the channel that will receive the challenges
+        return Ok(Response::new(ReceiverStream::new(rx)));
+    }
+
+    async fn get_token_status(
+        &self,
+        request: tonic::Request<()>,
+    ) -> Result<tonic::Response<youtube_service::TokenStatus>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let mut accounts = Vec::new();
+        let readers = tenant
+            .read_hubs
+            .iter()
+            .map(|(account, _)| *account)
+            .filter(|account| account.account == AuthAccount::Reader);
+        for account in [AccountId::BOT, AccountId::STREAMER]
+            .iter()
+            .copied()
+            .chain(readers)
+        {
+            let mut status = tenant
+                .token_statuses
+                .read()
+                .await
+                .get(&account)
+                .cloned()
+                .unwrap_or(AccountTokenStatus {
+                    account: account.account as i32,
+                    reader: account.reader,
+                    ..Default::default()
+                });
+            // While the device flow waits for the operator, the check itself doesn't return yet
+            let challenge = self.auth_challenges.get(&tenant.name, account).await;
+            if let Some(challenge) = challenge {
+                if challenge.state != AuthState::Authenticated as i32 {
+                    status.health = TokenHealth::TokenReauthRequired as i32;
+                }
+            }
+            accounts.push(status);
+        }
+        return Ok(Response::new(youtube_service::TokenStatus { accounts }));
+    }
+
+    async fn get_quota_status(
+        &self,
+        _: tonic::Request<()>,
+    ) -> Result<tonic::Response<youtube_service::QuotaStatus>, tonic::Status> {
+        let status_result = self.quota.status().await;
+        match status_result {
+            Ok(status) => return Ok(Response::new(status)),
+            Err(e) => return Err(Status::new(tonic::Code::Internal, e.to_string())),
+        }
+    }
+
+    async fn get_send_status(
         &self,
-        request: tonic::Request<String>,
-    ) -> Result<tonic::Response<()>, tonic::Status> {
-        // Build a livechat message
-        let message = request.into_inner();
-        let mut livechat_message = LiveChatMessage::default();
-        let mut livechat_snippet = LiveChatMessageSnippet::default();
-        let mut text_message_details = LiveChatTextMessageDetails::default();
-        livechat_snippet.type_ = Some("textMessageEvent".to_string());
-        livechat_snippet.live_chat_id = Some(self.livechat_id.clone());
-        text_message_details.message_text = Some(message);
-        livechat_snippet.text_message_details = Some(text_message_details);
-        livechat_message.snippet = Some(livechat_snippet);
-
-        // Send the message to the YouTube API
-        let response_result = self
-            .youtube_hub
-            .live_chat_messages()
-            .insert(livechat_message)
-            .add_part("snippet")
-            .doit()
-            .await;
-        // If there was an error, log it and return the error to the client
-        if let Err(e) = response_result {
-            let error_message = log_google_errors(e).await;
+        request: tonic::Request<youtube_service::SendStatusRequest>,
+    ) -> Result<tonic::Response<youtube_service::SendStatuses>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let idempotency_key = request.into_inner().idempotency_key;
+        if idempotency_key.is_empty() {
             return Err(Status::new(
-                tonic::Code::Internal,
-                error_message,
+                tonic::Code::InvalidArgument,
+                "An idempotency key is required",
             ));
         }
-        return Ok(Response::new(()));
+        let statuses_result = tenant.send_queue.statuses(&idempotency_key);
+        match statuses_result {
+            Ok(statuses) if statuses.is_empty() => {
+                return Err(Status::new(
+                    tonic::Code::NotFound,
+                    format!("No message was queued with the key {}", idempotency_key),
+                ));
+            }
+            Ok(statuses) => return Ok(Response::new(youtube_service::SendStatuses { statuses })),
+            Err(e) => {
+                error!("Error while reading send statuses: {}", e);
+                return Err(Status::new(tonic::Code::Internal, e.to_string()));
+            }
+        }
     }
 
-    type SubscribeMessagesStream = ReceiverStream<Result<YouTubeChatMessage, Status>>;
+    type SubscribeSendStatusStream = ReceiverStream<Result<SendStatus, Status>>;
 
-    async fn subscribe_messages(
+    async fn subscribe_send_status(
         &self,
-        _: tonic::Request<()>,
-    ) -> Result<tonic::Response<Self::SubscribeMessagesStream>, tonic::Status> {
-        // Create a pair of mpsc channels to send messages to the client
+        request: tonic::Request<youtube_service::SendStatusRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeSendStatusStream>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let idempotency_key = request.into_inner().idempotency_key;
+        // Create a pair of mpsc channels to send statuses to the client
         let (tx, rx) = mpsc::channel(4);
         // Create a receiver for the broadcast stream because we have a new listener
-        let mut message_rx = self.messages_tx.subscribe();
+        let mut status_rx = tenant.send_queue.subscribe();
 
-        // Spawn a future that will forward the messages from the broadcast channel to the mpsc channel
+        // Spawn a future that will forward the statuses from the broadcast channel to the mpsc channel
         tokio::spawn(async move {
-            while let Ok(message) = message_rx.recv().await {
+            while let Ok(status) = status_rx.recv().await {
+                if !idempotency_key.is_empty() && status.idempotency_key != idempotency_key {
+                    continue;
+                }
                 if tx.is_closed() {
                     debug!("Someone closed the channel. Good bye!");
                     break;
                 }
 
-                if let Err(e) = tx.send(Ok(message)).await {
-                    error!("Error sending message: {}", e);
+                if let Err(e) = tx.send(Ok(status)).await {
+                    error!("Error sending send status: {}", e);
                 }
             }
         });
 
-        // Return the channel that will receive the messages
+        // Return the channel that will receive the statuses
         return Ok(Response::new(ReceiverStream::new(rx)));
     }
 
-    async fn get_messages(
+    async fn list_timers(
         &self,
-        request: tonic::Request<youtube_service::GetMessageRequest>,
-    ) -> Result<tonic::Response<youtube_service::YouTubeChatMessages>, tonic::Status> {
-        let get_message_request = request.into_inner();
-        use crate::schema::livechat_messages::dsl::*;
+        request: tonic::Request<()>,
+    ) -> Result<tonic::Response<youtube_service::Timers>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        match tenant.timers.list() {
+            Ok(timers) => return Ok(Response::new(youtube_service::Timers { timers })),
+            Err(e) => {
+                error!("Error while reading timers: {}", e);
+                return Err(Status::new(tonic::Code::Internal, e.to_string()));
+            }
+        }
+    }
 
-        // Get messages from the database
-        let db_conn = &self.database_connection.get().unwrap();
-        let results = livechat_messages
-            .order(sent_at.desc())
-            .limit(get_message_request.limit.into())
-            .offset(get_message_request.offset.into())
-            .load::<LivechatMessage>(db_conn)
-            .unwrap();
-        let converted: Vec<YouTubeChatMessage> = results.iter().map(|m| m.into()).collect();
-        return Ok(Response::new(converted.into()));
+    async fn create_timer(
+        &self,
+        request: tonic::Request<Timer>,
+    ) -> Result<tonic::Response<Timer>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let timer = request.into_inner();
+        if let Err(e) = validate_timer(&timer) {
+            return Err(Status::new(tonic::Code::InvalidArgument, e));
+        }
+
+        match tenant.timers.create(&timer) {
+            Ok(Some(created_timer)) => {
+                info!(
+                    "Created timer {} of tenant {}",
+                    created_timer.name, tenant.name
+                );
+                return Ok(Response::new(created_timer));
+            }
+            Ok(None) => {
+                return Err(Status::new(
+                    tonic::Code::AlreadyExists,
+                    format!("There already is a timer named {}", timer.name.trim()),
+                ));
+            }
+            Err(e) => {
+                error!("Error while creating timer: {}", e);
+                return Err(Status::new(tonic::Code::Internal, e.to_string()));
+            }
+        }
+    }
+
+    async fn update_timer(
+        &self,
+        request: tonic::Request<Timer>,
+    ) -> Result<tonic::Response<Timer>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let timer = request.into_inner();
+        if let Err(e) = validate_timer(&timer) {
+            return Err(Status::new(tonic::Code::InvalidArgument, e));
+        }
+        // Renaming a timer must not clash with another one
+        match tenant.timers.name_taken(&timer.name, timer.timer_id) {
+            Ok(false) => {}
+            Ok(true) => {
+                return Err(Status::new(
+                    tonic::Code::AlreadyExists,
+                    format!("There already is a timer named {}", timer.name.trim()),
+                ));
+            }
+            Err(e) => {
+                error!("Error while reading timers: {}", e);
+                return Err(Status::new(tonic::Code::Internal, e.to_string()));
+            }
+        }
+
+        match tenant.timers.update(&timer) {
+            Ok(Some(updated_timer)) => {
+                info!(
+                    "Updated timer {} of tenant {}",
+                    updated_timer.name, tenant.name
+                );
+                return Ok(Response::new(updated_timer));
+            }
+            Ok(None) => {
+                return Err(Status::new(
+                    tonic::Code::NotFound,
+                    format!("There is no timer {}", timer.timer_id),
+                ));
+            }
+            Err(e) => {
+                error!("Error while updating timer: {}", e);
+                return Err(Status::new(tonic::Code::Internal, e.to_string()));
+            }
+        }
+    }
+
+    async fn delete_timer(
+        &self,
+        request: tonic::Request<youtube_service::DeleteTimerRequest>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let tenant = self.tenant(&request).await?;
+        let timer_id = request.into_inner().timer_id;
+        match tenant.timers.delete(timer_id) {
+            Ok(true) => {
+                info!("Deleted timer {} of tenant {}", timer_id, tenant.name);
+                return Ok(Response::new(()));
+            }
+            Ok(false) => {
+                return Err(Status::new(
+                    tonic::Code::NotFound,
+                    format!("There is no timer {}", timer_id),
+                ));
+            }
+            Err(e) => {
+                error!("Error while deleting timer: {}", e);
+                return Err(Status::new(tonic::Code::Internal, e.to_string()));
+            }
+        }
     }
 }
 
 pub fn insert_chat_message(
     database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant_name: &str,
     chat_message: &YouTubeChatMessage,
+    broadcast_id: Option<i32>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Check if the message already exists
     // If it does, do not insert it again
@@ -221,30 +1201,396 @@ pub fn insert_chat_message(
     }
 
     // Insert the message
-    let insert_message = InsertLivechatMessage::from(chat_message);
+    let mut insert_message = InsertLivechatMessage::from(chat_message);
+    insert_message.broadcast_id = broadcast_id;
     diesel::insert_into(schema::livechat_messages::table)
-        .values(insert_message)
+        .values((
+            insert_message,
+            schema::livechat_messages::tenant.eq(tenant_name),
+        ))
+        .execute(&database_connection.get()?)?;
+    Ok(())
+}
+
+/// Stores a message this service sent, flagged as such.
+/// If it already came back through the chat and was stored from there, the stored message is flagged instead.
+pub fn insert_own_message(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant_name: &str,
+    chat_message: &YouTubeChatMessage,
+    broadcast_id: Option<i32>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use schema::livechat_messages::dsl::{livechat_messages, sent_by_service, tenant, youtube_id};
+    let mut insert_message = InsertLivechatMessage::from(chat_message);
+    insert_message.broadcast_id = broadcast_id;
+    diesel::insert_into(livechat_messages)
+        .values((insert_message, tenant.eq(tenant_name)))
+        .on_conflict(youtube_id)
+        .do_update()
+        .set(sent_by_service.eq(true))
+        .execute(&database_connection.get()?)?;
+    Ok(())
+}
+
+/// Fills in the author details of a message this service sent, they are only known once it comes back from YouTube.
+/// Returns whether the message was sent by this service.
+pub fn complete_own_message(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant_name: &str,
+    chat_message: &YouTubeChatMessage,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    use schema::livechat_messages::dsl::*;
+    let updated_rows = diesel::update(
+        livechat_messages
+            .filter(tenant.eq(tenant_name))
+            .filter(youtube_id.eq(&chat_message.message_id))
+            .filter(sent_by_service.eq(true)),
+    )
+    .set((
+        channel_id.eq(&chat_message.channel_id),
+        display_name.eq(&chat_message.display_name),
+        is_chat_owner.eq(chat_message.is_chat_owner),
+        is_chat_moderator.eq(chat_message.is_chat_moderator),
+        is_chat_sponsor.eq(chat_message.is_chat_sponsor),
+        is_verified.eq(chat_message.is_verified),
+        profile_image_url
+            .eq(Some(chat_message.profile_image_url.clone()).filter(|url| !url.is_empty())),
+    ))
+    .execute(&database_connection.get()?)?;
+    Ok(updated_rows > 0)
+}
+
+pub fn insert_super_chat(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant_name: &str,
+    chat_message: &YouTubeChatMessage,
+    broadcast_id: Option<i32>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Check if the super chat already exists
+    // If it does, do not insert it again
+    use diesel::dsl::exists;
+    use diesel::select;
+    use schema::livechat_super_chats::dsl::{livechat_super_chats, youtube_id};
+    let exists: bool = select(exists(
+        livechat_super_chats.filter(youtube_id.eq(chat_message.message_id.clone())),
+    ))
+    .get_result(&database_connection.get()?)?;
+    if exists {
+        debug!(
+            "Skipping super chat with id {} because it already exists",
+            chat_message.message_id
+        );
+        return Ok(());
+    }
+
+    // Insert the super chat
+    let insert_super_chat = InsertLivechatSuperChat::from(chat_message);
+    diesel::insert_into(schema::livechat_super_chats::table)
+        .values((
+            insert_super_chat,
+            schema::livechat_super_chats::tenant.eq(tenant_name),
+            schema::livechat_super_chats::broadcast_id.eq(broadcast_id),
+        ))
+        .execute(&database_connection.get()?)?;
+    Ok(())
+}
+
+pub fn insert_membership_event(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant_name: &str,
+    chat_message: &YouTubeChatMessage,
+    broadcast_id: Option<i32>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Check if the membership event already exists
+    // If it does, do not insert it again
+    use diesel::dsl::exists;
+    use diesel::select;
+    use schema::livechat_membership_events::dsl::{livechat_membership_events, youtube_id};
+    let exists: bool = select(exists(
+        livechat_membership_events.filter(youtube_id.eq(chat_message.message_id.clone())),
+    ))
+    .get_result(&database_connection.get()?)?;
+    if exists {
+        debug!(
+            "Skipping membership event with id {} because it already exists",
+            chat_message.message_id
+        );
+        return Ok(());
+    }
+
+    // Insert the membership event
+    let insert_membership_event = InsertLivechatMembershipEvent::from(chat_message);
+    diesel::insert_into(schema::livechat_membership_events::table)
+        .values((
+            insert_membership_event,
+            schema::livechat_membership_events::tenant.eq(tenant_name),
+            schema::livechat_membership_events::broadcast_id.eq(broadcast_id),
+        ))
+        .execute(&database_connection.get()?)?;
+    Ok(())
+}
+
+/// Inserts the broadcast or updates it if it is already known and returns its database id
+pub fn upsert_broadcast(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant_name: &str,
+    insert_broadcast: &InsertBroadcast,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    use schema::broadcasts::dsl::{broadcast_id, broadcasts, tenant, youtube_id};
+    let row_id = diesel::insert_into(broadcasts)
+        .values((insert_broadcast, tenant.eq(tenant_name)))
+        .on_conflict(youtube_id)
+        .do_update()
+        .set(insert_broadcast)
+        .returning(broadcast_id)
+        .get_result(&database_connection.get()?)?;
+    Ok(row_id)
+}
+
+/// Looks up the broadcasts chosen by the selection policy of the tenant, stores them in the database and returns their livechats.
+/// Returns None if YouTube could not be asked.
+pub async fn find_selected_livechats(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant: &Tenant,
+) -> Option<Vec<Livechat>> {
+    let selection = tenant.broadcast_selection.get().await;
+    let mut livechats = Vec::new();
+    for broadcast in select_broadcasts(tenant, &selection).await? {
+        let livechat_id = match broadcast
+            .snippet
+            .as_ref()
+            .and_then(|snippet| snippet.live_chat_id.clone())
+        {
+            Some(livechat_id) => livechat_id,
+            None => continue,
+        };
+        let insert_broadcast = InsertBroadcast::from(&broadcast);
+        let broadcast_id =
+            match upsert_broadcast(database_connection, &tenant.name, &insert_broadcast) {
+                Ok(row_id) => Some(row_id),
+                Err(e) => {
+                    error!("Error while storing broadcast: {}", e);
+                    None
+                }
+            };
+        livechats.push(Livechat {
+            livechat_id,
+            broadcast_id,
+            broadcast_youtube_id: broadcast.id,
+        });
+    }
+    Some(livechats)
+}
+
+/// Marks the deleted message as deleted, whether it was a text message, a Super Chat, a Super Sticker or a membership message
+pub fn mark_chat_message_deleted(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant_name: &str,
+    deleted_message_id: &str,
+    moderator_channel_id: &str,
+    deleted_at_time: NaiveDateTime,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let connection = database_connection.get()?;
+    // Messages we never received simply won't be updated
+    {
+        use schema::livechat_messages::dsl::*;
+        diesel::update(
+            livechat_messages
+                .filter(tenant.eq(tenant_name))
+                .filter(youtube_id.eq(deleted_message_id)),
+        )
+        .set((
+            deleted_at.eq(deleted_at_time),
+            deleted_by_channel_id.eq(moderator_channel_id),
+        ))
+        .execute(&connection)?;
+    }
+    {
+        use schema::livechat_super_chats::dsl::*;
+        diesel::update(
+            livechat_super_chats
+                .filter(tenant.eq(tenant_name))
+                .filter(youtube_id.eq(deleted_message_id)),
+        )
+        .set((
+            deleted_at.eq(deleted_at_time),
+            deleted_by_channel_id.eq(moderator_channel_id),
+        ))
+        .execute(&connection)?;
+    }
+    {
+        use schema::livechat_membership_events::dsl::*;
+        diesel::update(
+            livechat_membership_events
+                .filter(tenant.eq(tenant_name))
+                .filter(youtube_id.eq(deleted_message_id)),
+        )
+        .set((
+            deleted_at.eq(deleted_at_time),
+            deleted_by_channel_id.eq(moderator_channel_id),
+        ))
+        .execute(&connection)?;
+    }
+    Ok(())
+}
+
+pub fn insert_ban(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+    tenant_name: &str,
+    event_id: &str,
+    user_banned: &UserBannedEvent,
+    banned_at: NaiveDateTime,
+    broadcast_id: Option<i32>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Check if the ban already exists
+    // If it does, do not insert it again
+    use diesel::dsl::exists;
+    use diesel::select;
+    use schema::livechat_bans::dsl::{livechat_bans, youtube_id};
+    let exists: bool = select(exists(livechat_bans.filter(youtube_id.eq(event_id))))
+        .get_result(&database_connection.get()?)?;
+    if exists {
+        debug!("Skipping ban with id {} because it already exists", event_id);
+        return Ok(());
+    }
+
+    // Insert the ban
+    let temporary = user_banned.ban_type == BanType::Temporary as i32;
+    let insert_ban = InsertLivechatBan {
+        youtube_id: event_id.to_string(),
+        banned_channel_id: user_banned.banned_channel_id.clone(),
+        banned_display_name: user_banned.banned_display_name.clone(),
+        ban_type: if temporary { "temporary" } else { "permanent" }.to_string(),
+        ban_duration_seconds: if temporary {
+            Some(user_banned.ban_duration_seconds as i64)
+        } else {
+            None
+        },
+        moderator_channel_id: user_banned.moderator_channel_id.clone(),
+        moderator_display_name: user_banned.moderator_display_name.clone(),
+        banned_at,
+        received_at: chrono::Utc::now().naive_utc(),
+    };
+    diesel::insert_into(schema::livechat_bans::table)
+        .values((
+            insert_ban,
+            schema::livechat_bans::tenant.eq(tenant_name),
+            schema::livechat_bans::broadcast_id.eq(broadcast_id),
+        ))
         .execute(&database_connection.get()?)?;
     Ok(())
 }
 
+/// Extracts the membership details of a membership related message.
+/// The API revision we are pinned to does not know about the details of gifting events yet,
+/// so those are taken from the gifting snippet fetched for the message by `fetch_gifting_details`.
+fn parse_membership_event(
+    message_type: &str,
+    message_snippet: LiveChatMessageSnippet,
+    gifting: GiftingSnippet,
+) -> (YouTubeChatMessageType, String, YouTubeMembershipDetails) {
+    let display_message = message_snippet.display_message.unwrap_or_default();
+    match message_type {
+        "newSponsorEvent" => {
+            let details = message_snippet.new_sponsor_details.unwrap_or_default();
+            (
+                YouTubeChatMessageType::NewSponsor,
+                display_message,
+                YouTubeMembershipDetails {
+                    member_level_name: details.member_level_name.unwrap_or_default(),
+                    ..Default::default()
+                },
+            )
+        }
+        "memberMilestoneChatEvent" => {
+            let details = message_snippet
+                .member_milestone_chat_details
+                .unwrap_or_default();
+            (
+                YouTubeChatMessageType::MemberMilestone,
+                details.user_comment.unwrap_or(display_message),
+                YouTubeMembershipDetails {
+                    member_level_name: details.member_level_name.unwrap_or_default(),
+                    member_month: details.member_month.unwrap_or_default(),
+                    ..Default::default()
+                },
+            )
+        }
+        "membershipGiftingEvent" => {
+            let details = gifting.membership_gifting_details.unwrap_or_default();
+            (
+                YouTubeChatMessageType::MembershipGifting,
+                display_message,
+                YouTubeMembershipDetails {
+                    member_level_name: details.gift_memberships_level_name.unwrap_or_default(),
+                    gift_count: details.gift_memberships_count.unwrap_or_default(),
+                    ..Default::default()
+                },
+            )
+        }
+        _ => {
+            let details = gifting.gift_membership_received_details.unwrap_or_default();
+            (
+                YouTubeChatMessageType::GiftMembershipReceived,
+                display_message,
+                YouTubeMembershipDetails {
+                    member_level_name: details.member_level_name.unwrap_or_default(),
+                    gifter_channel_id: details.gifter_channel_id.unwrap_or_default(),
+                    gifting_event_id: details
+                        .associated_membership_gifting_message_id
+                        .unwrap_or_default(),
+                    ..Default::default()
+                },
+            )
+        }
+    }
+}
+
+/// Fetches the messages of the livechat until the chat ends or YouTube returns an error.
 async fn fetch_messages(
-    bot_hub: &YouTube,
-    streamer_hub: &YouTube,
-    livechat_id: String,
-    tx: Sender<YouTubeChatMessage>,
+    tenant: &Tenant,
+    livechat: Livechat,
     pool: &Pool<ConnectionManager<PgConnection>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Clone the livechat id so we can change it later
-    let mut livechat_id_clone = livechat_id.clone();
-    // Initialize the page token to be empty
-    let mut page_token: Option<String> = None;
+    let tenant_name = tenant.name.as_str();
+    let tx = &tenant.events_tx;
+    let livechat_id = livechat.livechat_id;
+    let broadcast_id = livechat.broadcast_id;
+    // Resume where the last run stopped, or start with an empty page token
+    let (mut page_token, mut last_message_id) = match load_cursor(pool, tenant_name, &livechat_id) {
+        Ok(Some(cursor)) => {
+            info!(
+                "Resuming livechat {} after message {}",
+                livechat_id,
+                cursor.last_message_id.as_deref().unwrap_or("-")
+            );
+            (cursor.page_token, cursor.last_message_id)
+        }
+        Ok(None) => (None, None),
+        Err(e) => {
+            error!(
+                "Unable to load the cursor of livechat {}: {}",
+                livechat_id, e
+            );
+            (None, None)
+        }
+    };
+    // Without a page token YouTube returns the recent messages, the ones up to this id were processed already
+    let mut skip_until: Option<String> = if page_token.is_none() {
+        last_message_id.clone()
+    } else {
+        None
+    };
+    // How often the read credential was switched since the last successful poll
+    let mut rotations = 0;
+    let mut scheduler = PollingScheduler::new(tenant.polling_policy.clone());
     let mut rx = tx.subscribe();
-    // Loop forever or until the future is cancelled
+    // Loop until the chat ends or the future is cancelled
     loop {
-        // Prepare the query to the YouTube API
-        let mut prepare_livechat = bot_hub.live_chat_messages().list(
-            livechat_id_clone.as_str(),
+        // Prepare the query to the YouTube API, sending messages stays with the bot
+        let (read_account, read_hub) = tenant.read_hub();
+        let mut prepare_livechat = read_hub.live_chat_messages().list(
+            livechat_id.as_str(),
             &vec!["snippet".to_string(), "authorDetails".to_string()],
         );
         // If we have a page token, add it to the query
@@ -252,55 +1598,129 @@ async fn fetch_messages(
             prepare_livechat = prepare_livechat.page_token(page_token.as_ref().unwrap().as_str());
         }
         // Execute the query
+        tenant
+            .record_call(read_account, "liveChatMessages.list")
+            .await;
+        let requested_page_token = page_token.clone();
         let response_result = prepare_livechat.doit().await;
-        // If the query failed, spit out an error and continue
         if let Err(e) = response_result {
-            error!("Error while fetching chat messages: {}", e);
-            log_google_errors(e).await;
-
-            // Try to get the latest livechat id
-            info!("Trying to recover by receiving the latest livechat id");
-            let livechat_id_result = get_livechat_id(streamer_hub).await;
-            if livechat_id_result.is_none() {
-                // If we can't get the latest livechat id, wait for 10 seconds and try again
-                error!("Unable to get livechat ID! Retrying in 10 seconds...");
-                tokio::time::sleep(Duration::from_secs(10)).await;
-            } else {
-                livechat_id_clone = livechat_id_result.unwrap().clone();
+            let error_class = classify_google_error(&e);
+            // If the credential ran out of quota, retry right away with the next one until all of them were tried
+            if error_class == ErrorClass::Quota && rotations + 1 < tenant.read_hubs.len() {
+                warn!(
+                    "The {} account of tenant {} ran out of quota, polling with the next read credential",
+                    read_account, tenant_name
+                );
+                tenant.rotate_read_hub(read_account);
+                rotations += 1;
+                continue;
+            }
+            // A stored page token may have expired while the service was down, start over from the recent messages
+            if error_class == ErrorClass::Client && page_token.is_some() {
+                warn!(
+                    "The page token of livechat {} was rejected, continuing with the recent messages",
+                    livechat_id
+                );
+                page_token = None;
+                skip_until = last_message_id.clone();
+                continue;
+            }
+            // Retry as the class of the error allows, otherwise give up on this chat and let the ingestion loop decide whether to follow it again
+            let error_message = log_google_errors(e).await;
+            match scheduler.retry_delay(error_class) {
+                Some(delay) => {
+                    warn!(
+                        "Polling livechat {} failed with a {:?} error, retrying in {}ms",
+                        livechat_id,
+                        error_class,
+                        delay.as_millis()
+                    );
+                    tokio::time::sleep(delay).await;
+                    // Every credential may have quota again after waiting
+                    rotations = 0;
+                    continue;
+                }
+                None => return Err(error_message.into()),
             }
-            continue;
         }
+        rotations = 0;
         // Read the response
         let (response_body, response) = response_result.expect("response_result");
         let body_string = body_to_string(response_body).await;
         let items = response.items;
         if items.is_none() {
-            error!(
-                "Error while fetching chat messages: Items is none! Response: {}",
-                body_string
-            );
-
-            // Try to get the latest livechat id
-            info!("Trying to recover by receiving the latest livechat id");
-            let livechat_id_result = get_livechat_id(streamer_hub).await;
-            if livechat_id_result.is_none() {
-                // If we can't get the latest livechat id, wait for 10 seconds and try again
-                error!("Unable to get livechat ID! Retrying in 10 seconds...");
-                tokio::time::sleep(Duration::from_secs(10)).await;
-            } else {
-                livechat_id_clone = livechat_id_result.unwrap().clone();
-            }
-
-            continue;
+            return Err(format!("Items is none! Response: {}", body_string).into());
         }
         page_token = response.next_page_token;
-        let wait_for_millis = response.polling_interval_millis.unwrap();
-        let items = items.unwrap();
+        // Poll less often while the quota runs low or the chat is quiet
+        // Readers bring their own Google project, only the bot draws from the tracked quota
+        let polling_factor = if read_account == AccountId::BOT {
+            tenant.quota.polling_factor().await
+        } else {
+            1.0
+        };
+        let polling_interval = Duration::from_millis(
+            (response.polling_interval_millis.unwrap() as f64 * polling_factor) as u64,
+        );
+        let mut items = items.unwrap();
+        // Drop the messages that were processed before the restart
+        if let Some(skip_until_id) = skip_until.take() {
+            let seen = items
+                .iter()
+                .position(|msg| msg.id.as_deref() == Some(skip_until_id.as_str()));
+            if let Some(seen) = seen {
+                items.drain(..=seen);
+            }
+        }
+        let wait_for = scheduler.next_poll(polling_interval, items.len());
+        // The gifting details have to be fetched separately, only for the pages that have gifting events
+        let is_gifting_event = |msg: &LiveChatMessage| {
+            let message_type = msg
+                .snippet
+                .as_ref()
+                .and_then(|snippet| snippet.type_.as_deref());
+            matches!(
+                message_type,
+                Some("membershipGiftingEvent") | Some("giftMembershipReceivedEvent")
+            )
+        };
+        let has_gifting_events = items.iter().any(is_gifting_event);
+        let mut gifting_details = HashMap::new();
+        if has_gifting_events {
+            tenant
+                .record_call(read_account, "liveChatMessages.list")
+                .await;
+            match fetch_gifting_details(&read_hub, &livechat_id, requested_page_token.as_deref())
+                .await
+            {
+                Ok(details) => gifting_details = details,
+                Err(e) => error!(
+                    "Unable to fetch the gifting details of livechat {}: {}",
+                    livechat_id, e
+                ),
+            }
+        }
         // For each message in the response, send it to the broadcast channel
         for msg in items {
-            let author_details = msg.author_details.unwrap();
-            let channel_id = author_details.channel_id.unwrap();
-            let display_name = author_details.display_name.unwrap();
+            // The details are fetched with a second request, which may have returned a different page
+            if is_gifting_event(&msg) {
+                let message_id = msg.id.as_deref().unwrap_or_default();
+                if !gifting_details.contains_key(message_id) {
+                    warn!(
+                        "No gifting details for message {} of livechat {}, skipping it",
+                        message_id, livechat_id
+                    );
+                    continue;
+                }
+            }
+            let author_details = msg.author_details.unwrap_or_default();
+            let channel_id = author_details.channel_id.unwrap_or_default();
+            let display_name = author_details.display_name.unwrap_or_default();
+            let is_chat_owner = author_details.is_chat_owner.unwrap_or_default();
+            let is_chat_moderator = author_details.is_chat_moderator.unwrap_or_default();
+            let is_chat_sponsor = author_details.is_chat_sponsor.unwrap_or_default();
+            let is_verified = author_details.is_verified.unwrap_or_default();
+            let profile_image_url = author_details.profile_image_url.unwrap_or_default();
             let message_snippet = msg.snippet.unwrap();
             let message_type = message_snippet.type_.unwrap();
             let published_at = message_snippet.published_at.unwrap();
@@ -316,35 +1736,494 @@ async fn fetch_messages(
             };
             let message_id = msg.id.unwrap();
             debug!("Processing message {}", message_id);
+            last_message_id = Some(message_id.clone());
+
+            let event = match message_type.as_str() {
+                "textMessageEvent" => {
+                    // Create a chat message object, insert it into the database and send it to the broadcast channel
+                    let message_text = message_snippet.display_message.unwrap();
+                    info!("{} >> {}", display_name, message_text);
+                    let mut chat_message = YouTubeChatMessage {
+                        channel_id,
+                        display_name,
+                        message: message_text,
+                        sent_at_timestamp: Some(sent_at_timestamp.clone()),
+                        received_at_timestamp: Some(received_at_timestamp.clone()),
+                        message_id: message_id.clone(),
+                        message_type: YouTubeChatMessageType::TextMessage as i32,
+                        super_chat_details: None,
+                        membership_details: None,
+                        is_chat_owner,
+                        is_chat_moderator,
+                        is_chat_sponsor,
+                        is_verified,
+                        profile_image_url,
+                        deleted_at_timestamp: None,
+                        livechat_id: livechat_id.clone(),
+                        sent_by_service: false,
+                    };
+                    // Messages sent through this service were stored when they were sent, only the author details were missing
+                    match complete_own_message(pool, tenant_name, &chat_message) {
+                        Ok(sent_by_service) => chat_message.sent_by_service = sent_by_service,
+                        Err(e) => error!("Error while completing own message: {}", e),
+                    }
+                    let insert_result =
+                        insert_chat_message(pool, tenant_name, &chat_message, broadcast_id);
+                    if let Err(e) = insert_result {
+                        error!("Error while inserting chat message: {}", e);
+                    }
+                    Some(chat_event::Event::TextMessage(chat_message))
+                }
+                "superChatEvent" | "superStickerEvent" => {
+                    // Super Chats and Super Stickers carry mostly the same information, so both end up in the same table
+                    let (chat_message_type, user_comment, super_chat_details) =
+                        if message_type.as_str() == "superChatEvent" {
+                            let details = message_snippet.super_chat_details.unwrap();
+                            (
+                                YouTubeChatMessageType::SuperChat,
+                                details.user_comment.unwrap_or_default(),
+                                YouTubeSuperChatDetails {
+                                    amount_micros: details
+                                        .amount_micros
+                                        .unwrap_or_default()
+                                        .parse()
+                                        .unwrap_or_default(),
+                                    currency: details.currency.unwrap_or_default(),
+                                    amount_display_string: details
+                                        .amount_display_string
+                                        .unwrap_or_default(),
+                                    tier: details.tier.unwrap_or_default(),
+                                    sticker_id: String::new(),
+                                    sticker_alt_text: String::new(),
+                                },
+                            )
+                        } else {
+                            let details = message_snippet.super_sticker_details.unwrap();
+                            let sticker_metadata =
+                                details.super_sticker_metadata.unwrap_or_default();
+                            (
+                                YouTubeChatMessageType::SuperSticker,
+                                String::new(),
+                                YouTubeSuperChatDetails {
+                                    amount_micros: details
+                                        .amount_micros
+                                        .unwrap_or_default()
+                                        .parse()
+                                        .unwrap_or_default(),
+                                    currency: details.currency.unwrap_or_default(),
+                                    amount_display_string: details
+                                        .amount_display_string
+                                        .unwrap_or_default(),
+                                    tier: details.tier.unwrap_or_default(),
+                                    sticker_id: sticker_metadata.sticker_id.unwrap_or_default(),
+                                    sticker_alt_text: sticker_metadata.alt_text.unwrap_or_default(),
+                                },
+                            )
+                        };
+                    info!(
+                        "{} >> [{}] {}",
+                        display_name, super_chat_details.amount_display_string, user_comment
+                    );
+                    let chat_message = YouTubeChatMessage {
+                        channel_id,
+                        display_name,
+                        message: user_comment,
+                        sent_at_timestamp: Some(sent_at_timestamp.clone()),
+                        received_at_timestamp: Some(received_at_timestamp.clone()),
+                        message_id: message_id.clone(),
+                        message_type: chat_message_type as i32,
+                        super_chat_details: Some(super_chat_details),
+                        membership_details: None,
+                        is_chat_owner,
+                        is_chat_moderator,
+                        is_chat_sponsor,
+                        is_verified,
+                        profile_image_url,
+                        deleted_at_timestamp: None,
+                        livechat_id: livechat_id.clone(),
+                        sent_by_service: false,
+                    };
+                    let insert_result =
+                        insert_super_chat(pool, tenant_name, &chat_message, broadcast_id);
+                    if let Err(e) = insert_result {
+                        error!("Error while inserting super chat: {}", e);
+                    }
+                    if chat_message_type == YouTubeChatMessageType::SuperChat {
+                        Some(chat_event::Event::SuperChat(chat_message))
+                    } else {
+                        Some(chat_event::Event::SuperSticker(chat_message))
+                    }
+                }
+                "newSponsorEvent"
+                | "memberMilestoneChatEvent"
+                | "membershipGiftingEvent"
+                | "giftMembershipReceivedEvent" => {
+                    let (chat_message_type, message_text, membership_details) =
+                        parse_membership_event(
+                            message_type.as_str(),
+                            message_snippet,
+                            gifting_details.remove(&message_id).unwrap_or_default(),
+                        );
+                    info!("{} >> [{}] {}", display_name, message_type, message_text);
+                    let chat_message = YouTubeChatMessage {
+                        channel_id,
+                        display_name,
+                        message: message_text,
+                        sent_at_timestamp: Some(sent_at_timestamp.clone()),
+                        received_at_timestamp: Some(received_at_timestamp.clone()),
+                        message_id: message_id.clone(),
+                        message_type: chat_message_type as i32,
+                        super_chat_details: None,
+                        membership_details: Some(membership_details),
+                        is_chat_owner,
+                        is_chat_moderator,
+                        is_chat_sponsor,
+                        is_verified,
+                        profile_image_url,
+                        deleted_at_timestamp: None,
+                        livechat_id: livechat_id.clone(),
+                        sent_by_service: false,
+                    };
+                    let insert_result =
+                        insert_membership_event(pool, tenant_name, &chat_message, broadcast_id);
+                    if let Err(e) = insert_result {
+                        error!("Error while inserting membership event: {}", e);
+                    }
+                    Some(chat_event::Event::Membership(chat_message))
+                }
+                "messageDeletedEvent" => {
+                    let details = message_snippet.message_deleted_details.unwrap_or_default();
+                    let deleted_message_id = details.deleted_message_id.unwrap_or_default();
+                    info!("{} deleted message {}", display_name, deleted_message_id);
+                    let update_result = mark_chat_message_deleted(
+                        pool,
+                        tenant_name,
+                        deleted_message_id.as_str(),
+                        channel_id.as_str(),
+                        sent_at.naive_utc(),
+                    );
+                    if let Err(e) = update_result {
+                        error!("Error while marking chat message as deleted: {}", e);
+                    }
+                    Some(chat_event::Event::MessageDeleted(MessageDeletedEvent {
+                        deleted_message_id,
+                        moderator_channel_id: channel_id,
+                        moderator_display_name: display_name,
+                    }))
+                }
+                "userBannedEvent" => {
+                    let details = message_snippet.user_banned_details.unwrap_or_default();
+                    let banned_user = details.banned_user_details.unwrap_or_default();
+                    let ban_type = if details.ban_type.as_deref() == Some("temporary") {
+                        BanType::Temporary
+                    } else {
+                        BanType::Permanent
+                    };
+                    let user_banned = UserBannedEvent {
+                        banned_channel_id: banned_user.channel_id.unwrap_or_default(),
+                        banned_display_name: banned_user.display_name.unwrap_or_default(),
+                        ban_type: ban_type as i32,
+                        ban_duration_seconds: details
+                            .ban_duration_seconds
+                            .unwrap_or_default()
+                            .parse()
+                            .unwrap_or_default(),
+                        moderator_channel_id: channel_id,
+                        moderator_display_name: display_name,
+                    };
+                    info!(
+                        "{} banned {} ({:?})",
+                        user_banned.moderator_display_name,
+                        user_banned.banned_display_name,
+                        ban_type
+                    );
+                    let insert_result = insert_ban(
+                        pool,
+                        tenant_name,
+                        message_id.as_str(),
+                        &user_banned,
+                        sent_at.naive_utc(),
+                        broadcast_id,
+                    );
+                    if let Err(e) = insert_result {
+                        error!("Error while inserting ban: {}", e);
+                    }
+                    Some(chat_event::Event::UserBanned(user_banned))
+                }
+                "chatEndedEvent" => {
+                    info!("The chat has ended");
+                    Some(chat_event::Event::ChatEnded(ChatEndedEvent {}))
+                }
+                "sponsorOnlyModeStartedEvent" | "sponsorOnlyModeEndedEvent" => {
+                    let enabled = message_type.as_str() == "sponsorOnlyModeStartedEvent";
+                    info!("Members-only mode enabled: {}", enabled);
+                    Some(chat_event::Event::ModeChanged(ChatModeChangedEvent {
+                        mode: ChatMode::MembersOnly as i32,
+                        enabled,
+                    }))
+                }
+                _ => {
+                    debug!("Ignoring message of type {}", message_type);
+                    None
+                }
+            };
 
-            if message_type.as_str() == "textMessageEvent" {
-                // Create a chat message object, insert it into the database and send it to the broadcast channel
-                let message_text = message_snippet.display_message.unwrap();
-                info!("{} >> {}", display_name, message_text);
-                let chat_message = YouTubeChatMessage {
-                    channel_id,
-                    display_name,
-                    message: message_text,
+            // Wrap the event in an envelope and send it to the broadcast channel
+            if let Some(event) = event {
+                let chat_ended = matches!(event, chat_event::Event::ChatEnded(_));
+                let chat_event = ChatEvent {
+                    event_id: message_id,
+                    livechat_id: livechat_id.clone(),
                     sent_at_timestamp: Some(sent_at_timestamp),
                     received_at_timestamp: Some(received_at_timestamp),
-                    message_id,
+                    event: Some(event),
                 };
-                let insert_result = insert_chat_message(pool, &chat_message);
-                if let Err(e) = insert_result {
-                    error!("Error while inserting chat message: {}", e);
-                }
-                debug!("Sending message...");
-                tx.send(chat_message)?;
+                debug!("Sending event...");
+                tx.send(chat_event)?;
                 let _ = rx.recv().await;
+                // Nothing will be posted to this chat anymore
+                if chat_ended {
+                    if let Err(e) = delete_cursor(pool, tenant_name, &livechat_id) {
+                        error!(
+                            "Unable to delete the cursor of livechat {}: {}",
+                            livechat_id, e
+                        );
+                    }
+                    return Ok(());
+                }
             }
         }
 
+        // Remember where to continue after a restart
+        if let Err(e) = store_cursor(
+            pool,
+            tenant_name,
+            &livechat_id,
+            page_token.as_deref(),
+            last_message_id.as_deref(),
+        ) {
+            error!(
+                "Unable to store the cursor of livechat {}: {}",
+                livechat_id, e
+            );
+        }
+
         // Wait for the amount of time specified by the API before requesting again
-        debug!("Waiting for {}ms before requesting again", wait_for_millis);
-        tokio::time::sleep(Duration::from_millis(wait_for_millis.into())).await;
+        debug!(
+            "Waiting for {}ms before requesting again",
+            wait_for.as_millis()
+        );
+        tokio::time::sleep(wait_for).await;
+    }
+}
+
+/// Waits until the broadcast watcher reports a broadcast that is upcoming or went live
+async fn wait_for_broadcast(state_rx: &mut tokio::sync::broadcast::Receiver<BroadcastStateChange>) {
+    loop {
+        match state_rx.recv().await {
+            Ok(state)
+                if state.status == BroadcastStatus::Live as i32
+                    || state.status == BroadcastStatus::Upcoming as i32 =>
+            {
+                return
+            }
+            Ok(_) => {}
+            // The missed changes may have been anything
+            Err(RecvError::Lagged(_)) => return,
+            // Without the watcher, nothing is reported anymore
+            Err(RecvError::Closed) => std::future::pending().await,
+        }
+    }
+}
+
+/// Waits until a new broadcast selection is configured
+async fn wait_for_selection_change(
+    selection_rx: &mut tokio::sync::broadcast::Receiver<BroadcastSelection>,
+) {
+    match selection_rx.recv().await {
+        Ok(_) | Err(RecvError::Lagged(_)) => {}
+        // Without a sender, the selection can't change anymore
+        Err(RecvError::Closed) => std::future::pending().await,
+    }
+}
+
+/// Follows a single livechat until it ends and reports its livechat id back to the ingestion loop afterwards
+async fn ingest_livechat(
+    tenant: Tenant,
+    livechat: Livechat,
+    pool: Pool<ConnectionManager<PgConnection>>,
+    done_tx: mpsc::Sender<String>,
+) {
+    let livechat_id = livechat.livechat_id.clone();
+    let failed = match fetch_messages(&tenant, livechat, &pool).await {
+        Ok(()) => false,
+        Err(e) => {
+            error!(
+                "Error while fetching messages of livechat {}: {}",
+                livechat_id, e
+            );
+            true
+        }
+    };
+    if failed {
+        // Don't hammer the API if the chat is still selected and fails right away again
+        let refollow_delay = jitter(tenant.polling_policy.refollow_delay);
+        info!(
+            "Following livechat {} again in {}ms",
+            livechat_id,
+            refollow_delay.as_millis()
+        );
+        tokio::time::sleep(refollow_delay).await;
+    }
+    let _ = done_tx.send(livechat_id).await;
+}
+
+/// Follows the livechats of the broadcasts the tenant selected, each in its own task, as soon as there are any.
+/// Chats that end are dropped, chats that are no longer selected after the selection changed are stopped.
+async fn ingest_livechats(tenant: &Tenant, pool: Pool<ConnectionManager<PgConnection>>) {
+    let livechats = &tenant.livechats;
+    // Livechat ids given through the environment variable have no known broadcast and are only followed until they end
+    let fixed_livechat_ids: HashSet<String> = tenant_env_var(&tenant.name, "LIVECHAT_ID")
+        .unwrap_or_default()
+        .split(',')
+        .map(|livechat_id| livechat_id.trim().to_string())
+        .filter(|livechat_id| !livechat_id.is_empty())
+        .collect();
+    let mut pending_fixed_livechats: Vec<Livechat> = fixed_livechat_ids
+        .iter()
+        .map(|livechat_id| Livechat {
+            livechat_id: livechat_id.clone(),
+            ..Default::default()
+        })
+        .collect();
+    let mut state_rx = tenant.broadcast_state_tx.subscribe();
+    let mut selection_rx = tenant.broadcast_selection.subscribe();
+    let (done_tx, mut done_rx) = mpsc::channel(16);
+    let mut ingesters: HashMap<String, JoinHandle<()>> = HashMap::new();
+    let mut selection_changed = false;
+    loop {
+        let selected_livechats = find_selected_livechats(&pool, tenant).await;
+        let selection_failed = selected_livechats.is_none();
+        let mut selected_livechats = selected_livechats.unwrap_or_default();
+        selected_livechats.append(&mut pending_fixed_livechats);
+
+        // Stop following chats that are not selected anymore, as long as it is known what is selected
+        if selection_changed && !selection_failed {
+            let selected_ids: HashSet<&String> = selected_livechats
+                .iter()
+                .map(|livechat| &livechat.livechat_id)
+                .collect();
+            let deselected_ids: Vec<String> = ingesters
+                .keys()
+                .filter(|livechat_id| {
+                    !selected_ids.contains(livechat_id)
+                        && !fixed_livechat_ids.contains(*livechat_id)
+                })
+                .cloned()
+                .collect();
+            for livechat_id in deselected_ids {
+                info!("Livechat {} is not selected anymore, stopping", livechat_id);
+                if let Some(ingester) = ingesters.remove(&livechat_id) {
+                    ingester.abort();
+                }
+                livechats.write().await.remove(&livechat_id);
+            }
+            selection_changed = false;
+        }
+
+        // Start following newly selected chats
+        for livechat in selected_livechats {
+            if ingesters.contains_key(&livechat.livechat_id) {
+                continue;
+            }
+            info!(
+                "Following livechat {} of tenant {}",
+                livechat.livechat_id, tenant.name
+            );
+            livechats
+                .write()
+                .await
+                .insert(livechat.livechat_id.clone(), livechat.clone());
+            let ingester = tokio::spawn(ingest_livechat(
+                tenant.clone(),
+                livechat.clone(),
+                pool.clone(),
+                done_tx.clone(),
+            ));
+            ingesters.insert(livechat.livechat_id, ingester);
+        }
+
+        // While a single broadcast is followed, there is no need to look for other ones
+        let following_all = tenant.broadcast_selection.get().await.policy
+            == BroadcastSelectionPolicy::AllActive as i32;
+        let idle = ingesters
+            .keys()
+            .all(|livechat_id| fixed_livechat_ids.contains(livechat_id));
+        let waiting_interval = tenant.polling_policy.waiting_interval;
+        if selection_failed {
+            info!(
+                "Unable to select the broadcasts of tenant {}, trying again in {}ms",
+                tenant.name,
+                waiting_interval.as_millis()
+            );
+        } else if ingesters.is_empty() {
+            info!("Waiting for a broadcast of tenant {}", tenant.name);
+        }
+        // The broadcast watcher already checks the broadcasts, so they are only selected again once it reports a change
+        tokio::select! {
+            _ = wait_for_broadcast(&mut state_rx), if idle || following_all => {}
+            _ = tokio::time::sleep(waiting_interval), if selection_failed => {}
+            _ = wait_for_selection_change(&mut selection_rx) => {
+                info!("Broadcast selection changed, looking for the selected broadcasts");
+                selection_changed = true;
+            }
+            Some(livechat_id) = done_rx.recv() => {
+                info!("Stopped following livechat {}", livechat_id);
+                ingesters.remove(&livechat_id);
+                livechats.write().await.remove(&livechat_id);
+            }
+        }
     }
 }
 
+/// Authenticates the accounts of a tenant, then serves it, follows its chats, sends its queued messages and watches its broadcasts and tokens
+async fn run_tenant(
+    tenant_name: &str,
+    tenants: &Tenants,
+    auth_challenges: &AuthChallenges,
+    token_storage: &TokenStorage,
+    quota: &QuotaTracker,
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+) {
+    let tenant = match Tenant::new(
+        tenant_name,
+        auth_challenges,
+        token_storage,
+        quota,
+        database_connection,
+    )
+    .await
+    {
+        Ok(tenant) => tenant,
+        Err(e) => {
+            error!("Unable to set up tenant {}: {}", tenant_name, e);
+            return;
+        }
+    };
+    tenants
+        .write()
+        .await
+        .insert(tenant_name.to_string(), tenant.clone());
+    info!("Tenant {} is authenticated and ready", tenant_name);
+    tokio::join!(
+        ingest_livechats(&tenant, database_connection.clone()),
+        watch_broadcasts(database_connection, &tenant),
+        watch_tokens(&tenant, auth_challenges),
+        deliver_messages(&tenant),
+        run_timers(&tenant)
+    );
+}
+
 pub fn connect_to_database() -> Pool<ConnectionManager<PgConnection>> {
     // Get the database URL from the environment
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
@@ -378,48 +2257,44 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let str_addr = osstr_addr.into_string().unwrap();
         addr = str_addr.parse()?;
     }
-    // Create 2 hubs for the YouTube API (authenticates automatically with the youtube scope)
-    let (bot_hub, streamer_hub) = authenticate_google().await?;
-    // Wrap the hub in an atomic reference counter to share it safetly across threads
-    let bot_hub_arc = Arc::new(bot_hub);
-    let streamer_hub_arc = Arc::new(streamer_hub);
-
-    // Get livechat id from either the environment variable or the currently running broadcast, if there is one
-    // If there is no currently running broadcast, the program will try again until it finds one
-    // Until then, the program will halt and the gRPC server will not run
-    let livechat_id: String;
-    let mut livechat_id_opt: Option<String> = None;
-    while livechat_id_opt.is_none() {
-        livechat_id_opt = if env::var("YTS_LIVECHAT_ID").is_ok() {
-            Some(env::var("YTS_LIVECHAT_ID").unwrap())
-        } else {
-            get_livechat_id(&streamer_hub_arc).await
-        };
-        if livechat_id_opt.is_none() {
-            error!("Unable to determine livechat ID, retrying in 30 seconds");
-            tokio::time::sleep(Duration::from_secs(30)).await;
-        }
-    }
-    livechat_id = livechat_id_opt.unwrap();
-
-    info!("Livechat ID determined: {}", livechat_id);
-
-    // Create a broadcast channel to send messages across futures
-    let (tx, _) = tokio::sync::broadcast::channel(100);
+    // Every tenant authenticates its own streamer and bot account with the youtube scope
+    // Until then, the gRPC server already runs, hands out the auth challenges and serves what is stored in the database
+    // The livechats of a tenant are determined by its ingestion loop once there are selected broadcasts
+    let tenant_names = tenant_names_from_env();
+    let tenants = Tenants::default();
+    let auth_challenges = AuthChallenges::default();
+    let token_storage = token_storage_from_env(&db_connection);
+    // The quota of the Google project is shared by all tenants
+    let quota = quota_tracker_from_env(&db_connection);
     // Create a service implementation
     let service = YouTubeServiceImpl::new(
-        tx.clone(),
-        bot_hub_arc.clone(),
-        livechat_id.clone(),
+        tenant_names.clone(),
+        tenants.clone(),
+        auth_challenges.clone(),
+        admin_clients_from_env(),
+        quota.clone(),
         db_connection.clone(),
     );
 
-    // Spawn the gRPC server future with our service implementation as well as our fetch function future
-    let (_, _) = tokio::join!(
+    // Every tenant authenticates and runs on its own, a tenant waiting for authentication doesn't hold up the others
+    let tenant_futures = tenant_names.iter().map(|tenant_name| {
+        run_tenant(
+            tenant_name,
+            &tenants,
+            &auth_challenges,
+            &token_storage,
+            &quota,
+            &db_connection,
+        )
+    });
+
+    // Spawn the gRPC server future with our service implementation as well as the futures that run every tenant
+    let (_, _, _) = tokio::join!(
         Server::builder()
             .add_service(YouTubeServiceServer::new(service))
             .serve(addr),
-        fetch_messages(&bot_hub_arc, &streamer_hub_arc, livechat_id, tx, &db_connection)
+        join_all(tenant_futures),
+        serve_metrics(quota.clone())
     );
 
     Ok(())
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use diesel::pg::PgConnection;
+use diesel::r2d2::ConnectionManager;
+use google_youtube3::YouTube;
+use r2d2::Pool;
+use tokio::sync::broadcast::Sender;
+use tokio::sync::RwLock;
+use tonic::Status;
+
+use crate::auth::{AccountId, AuthChallenges};
+use crate::broadcast::BroadcastStates;
+use crate::permissions::{sender_permissions_from_env, SenderPermissions};
+use crate::polling::{polling_policy_from_env, PollingPolicy};
+use crate::quota::QuotaTracker;
+use crate::selection::{broadcast_selection_from_env, SharedBroadcastSelection};
+use crate::send_queue::SendQueue;
+use crate::timers::TimerStore;
+use crate::token_health::TokenStatuses;
+use crate::token_storage::TokenStorage;
+use crate::youtube::{authenticate_google, authenticate_readers};
+use crate::youtube_service::{BroadcastStateChange, ChatEvent};
+use crate::IngestedLivechats;
+
+/// The tenants that are authenticated and served, keyed by their name
+pub type Tenants = Arc<RwLock<HashMap<String, Tenant>>>;
+
+/// The tenant used if YTS_TENANTS is not set. It keeps the token caches and data of single channel deployments.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// The request metadata clients choose the tenant with
+pub const TENANT_METADATA_KEY: &str = "x-tenant";
+
+/// A YouTube channel served by this deployment, with its own streamer and bot account, chats and broadcasts
+#[derive(Clone)]
+pub struct Tenant {
+    pub name: String,
+    pub bot_hub: Arc<YouTube>,
+    pub streamer_hub: Arc<YouTube>,
+    /// The credentials chats are polled with, the bot comes first and the readers follow
+    pub read_hubs: Arc<Vec<(AccountId, Arc<YouTube>)>>,
+    /// The index of the read credential currently in use
+    read_hub_index: Arc<AtomicUsize>,
+    pub events_tx: Sender<ChatEvent>,
+    pub livechats: IngestedLivechats,
+    pub broadcast_selection: SharedBroadcastSelection,
+    pub polling_policy: PollingPolicy,
+    pub broadcast_state_tx: Sender<BroadcastStateChange>,
+    pub broadcast_states: BroadcastStates,
+    pub token_statuses: TokenStatuses,
+    pub quota: QuotaTracker,
+    pub send_queue: SendQueue,
+    pub sender_permissions: SenderPermissions,
+    pub timers: TimerStore,
+}
+
+impl Tenant {
+    /// Authenticates the streamer, bot and reader accounts of the tenant and sets up everything its chats need.
+    /// This only returns once all accounts are authenticated.
+    pub async fn new(
+        name: &str,
+        challenges: &AuthChallenges,
+        token_storage: &TokenStorage,
+        quota: &QuotaTracker,
+        database_connection: &Pool<ConnectionManager<PgConnection>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // A broken configuration should show up before anybody is asked to authenticate
+        let broadcast_selection = broadcast_selection_from_env(name)?;
+        let sender_permissions = sender_permissions_from_env(name)?;
+        let (bot_hub, streamer_hub) = authenticate_google(name, challenges, token_storage).await?;
+        let readers = authenticate_readers(name, challenges, token_storage).await?;
+        // Wrap the hubs in an atomic reference counter to share them safetly across threads
+        let bot_hub = Arc::new(bot_hub);
+        let mut read_hubs = vec![(AccountId::BOT, bot_hub.clone())];
+        read_hubs.extend(
+            readers
+                .into_iter()
+                .map(|(account, hub)| (account, Arc::new(hub))),
+        );
+        // Create a broadcast channel to send messages across futures
+        let (events_tx, _) = tokio::sync::broadcast::channel(100);
+        // Create a broadcast channel to send broadcast state changes across futures
+        let (broadcast_state_tx, _) = tokio::sync::broadcast::channel(16);
+        Ok(Tenant {
+            name: name.to_string(),
+            bot_hub,
+            streamer_hub: Arc::new(streamer_hub),
+            read_hubs: Arc::new(read_hubs),
+            read_hub_index: Arc::new(AtomicUsize::new(0)),
+            events_tx,
+            livechats: IngestedLivechats::default(),
+            broadcast_selection: SharedBroadcastSelection::new(broadcast_selection),
+            polling_policy: polling_policy_from_env(name),
+            broadcast_state_tx,
+            broadcast_states: BroadcastStates::default(),
+            token_statuses: TokenStatuses::default(),
+            quota: quota.clone(),
+            send_queue: SendQueue::new(database_connection, name),
+            sender_permissions,
+            timers: TimerStore::new(database_connection, name),
+        })
+    }
+
+    /// Records a call of the YouTube API method by one of the accounts of the tenant against the quota
+    pub async fn record_call(&self, account: AccountId, method: &str) {
+        self.quota.record(&self.name, account, method).await;
+    }
+
+    /// Returns the read credential chats are currently polled with
+    pub fn read_hub(&self) -> (AccountId, Arc<YouTube>) {
+        let index = self.read_hub_index.load(Ordering::Relaxed) % self.read_hubs.len();
+        self.read_hubs[index].clone()
+    }
+
+    /// Moves on to the next read credential because the failed one ran out of quota.
+    /// If another chat already moved on, the current credential is kept.
+    pub fn rotate_read_hub(&self, failed: AccountId) {
+        let index = self.read_hub_index.load(Ordering::Relaxed) % self.read_hubs.len();
+        if self.read_hubs[index].0 == failed {
+            let _ = self.read_hub_index.compare_exchange(
+                index,
+                (index + 1) % self.read_hubs.len(),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+        }
+    }
+
+    /// Returns the ids of the livechats a request targets, which are all followed chats if no livechat id was given
+    pub async fn target_livechat_ids(&self, livechat_id: &str) -> Result<Vec<String>, Status> {
+        let livechats = self.livechats.read().await;
+        if livechats.is_empty() {
+            return Err(Status::new(
+                tonic::Code::FailedPrecondition,
+                "Waiting for a broadcast, there is no livechat yet",
+            ));
+        }
+        if livechat_id.is_empty() {
+            return Ok(livechats.keys().cloned().collect());
+        }
+        if !livechats.contains_key(livechat_id) {
+            return Err(Status::new(
+                tonic::Code::NotFound,
+                format!("Livechat {} is not followed", livechat_id),
+            ));
+        }
+        Ok(vec![livechat_id.to_string()])
+    }
+
+    /// Returns the id of the single livechat a request targets.
+    /// Without a livechat id, this only works while exactly one chat is followed.
+    pub async fn target_livechat_id(&self, livechat_id: &str) -> Result<String, Status> {
+        let mut livechat_ids = self.target_livechat_ids(livechat_id).await?;
+        if livechat_ids.len() > 1 {
+            return Err(Status::new(
+                tonic::Code::InvalidArgument,
+                "Several livechats are followed, a livechat id is required",
+            ));
+        }
+        Ok(livechat_ids.remove(0))
+    }
+}
+
+/// Returns the names of the tenants from YTS_TENANTS, a comma separated list, or only the default tenant
+pub fn tenant_names_from_env() -> Vec<String> {
+    let names: Vec<String> = env::var("YTS_TENANTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    if names.is_empty() {
+        return vec![DEFAULT_TENANT.to_string()];
+    }
+    names
+}
+
+/// Reads a setting of the tenant from the environment.
+/// YTS_<TENANT>_<NAME> takes precedence over YTS_<NAME>, which applies to every tenant.
+pub fn tenant_env_var(tenant: &str, name: &str) -> Option<String> {
+    let tenant_key: String = tenant
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    env::var(format!("YTS_{}_{}", tenant_key, name))
+        .or_else(|_| env::var(format!("YTS_{}", name)))
+        .ok()
+}
+
+/// Finds the name of the tenant a request is meant for through its metadata.
+/// Clients may leave the metadata out while there is only one tenant.
+pub fn find_tenant_name<T>(
+    tenant_names: &[String],
+    request: &tonic::Request<T>,
+) -> Result<String, Status> {
+    let tenant_name = request
+        .metadata()
+        .get(TENANT_METADATA_KEY)
+        .and_then(|value| value.to_str().ok());
+    match tenant_name {
+        Some(tenant_name) if tenant_names.iter().any(|name| name == tenant_name) => {
+            Ok(tenant_name.to_string())
+        }
+        Some(tenant_name) => Err(Status::new(
+            tonic::Code::NotFound,
+            format!("Unknown tenant {}", tenant_name),
+        )),
+        None if tenant_names.len() == 1 => Ok(tenant_names[0].clone()),
+        None => Err(Status::new(
+            tonic::Code::InvalidArgument,
+            format!(
+                "Several tenants are registered, the {} metadata is required",
+                TENANT_METADATA_KEY
+            ),
+        )),
+    }
+}
+
+/// Finds the tenant a request is meant for, see `find_tenant_name`.
+/// Tenants whose accounts are not authenticated yet are unavailable.
+pub async fn find_tenant<T>(
+    tenant_names: &[String],
+    tenants: &Tenants,
+    request: &tonic::Request<T>,
+) -> Result<Tenant, Status> {
+    let tenant_name = find_tenant_name(tenant_names, request)?;
+    tenants
+        .read()
+        .await
+        .get(&tenant_name)
+        .cloned()
+        .ok_or_else(|| {
+            Status::new(
+                tonic::Code::Unavailable,
+                format!(
+                    "The accounts of tenant {} are not authenticated yet",
+                    tenant_name
+                ),
+            )
+        })
+}
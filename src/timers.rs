@@ -0,0 +1,411 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use cron::Schedule;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use log::{debug, error, info};
+use r2d2::Pool;
+use tokio::sync::Notify;
+
+use crate::auth::{to_timestamp, AccountId};
+use crate::message_text::{message_length, MAX_MESSAGE_LENGTH};
+use crate::models::{ChatTimer, InsertChatTimer};
+use crate::schema;
+use crate::tenant::Tenant;
+use crate::youtube_service::{BroadcastStatus, MessagePriority, Timer};
+
+/// Timers can't run more often than this, so announcements don't drown the chat
+const MIN_INTERVAL_SECONDS: i32 = 60;
+
+/// Checks that the timer has a message YouTube accepts and exactly one valid schedule
+pub fn validate_timer(timer: &Timer) -> Result<(), String> {
+    if timer.name.trim().is_empty() {
+        return Err("a timer requires a name".to_string());
+    }
+    let length = message_length(timer.message.trim());
+    if length == 0 {
+        return Err("a timer requires a message".to_string());
+    }
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(format!(
+            "the message is {} characters long, YouTube allows {}",
+            length, MAX_MESSAGE_LENGTH
+        ));
+    }
+    if timer.min_messages < 0 {
+        return Err("the minimum number of messages can't be negative".to_string());
+    }
+    match (
+        timer.cron_schedule.trim().is_empty(),
+        timer.interval_seconds,
+    ) {
+        (true, 0) => Err("a timer requires a cron schedule or an interval".to_string()),
+        (false, 0) => {
+            let schedule = Schedule::from_str(timer.cron_schedule.trim())
+                .map_err(|e| format!("invalid cron schedule: {}", e))?;
+            // Runs of schedules like "*/10 0 * * * *" are not evenly spaced, so several upcoming runs are compared
+            let upcoming_runs: Vec<DateTime<Utc>> = schedule.upcoming(Utc).take(10).collect();
+            let too_often = upcoming_runs.windows(2).any(|runs| {
+                runs[1] - runs[0] < chrono::Duration::seconds(MIN_INTERVAL_SECONDS as i64)
+            });
+            if too_often {
+                return Err(format!(
+                    "the cron schedule has to leave at least {} seconds between runs",
+                    MIN_INTERVAL_SECONDS
+                ));
+            }
+            Ok(())
+        }
+        (true, interval_seconds) if interval_seconds < MIN_INTERVAL_SECONDS => Err(format!(
+            "the interval has to be at least {} seconds",
+            MIN_INTERVAL_SECONDS
+        )),
+        (true, _) => Ok(()),
+        (false, _) => Err("a timer has either a cron schedule or an interval".to_string()),
+    }
+}
+
+/// Returns when a timer with the schedule runs next after the given time, None if a cron schedule has no further runs
+fn next_run_after(
+    cron_schedule: &Option<String>,
+    interval_seconds: Option<i32>,
+    after: NaiveDateTime,
+) -> Option<NaiveDateTime> {
+    match (cron_schedule, interval_seconds) {
+        (Some(cron_schedule), _) => Schedule::from_str(cron_schedule)
+            .ok()?
+            .after(&DateTime::<Utc>::from_utc(after, Utc))
+            .next()
+            .map(|next_run_at| next_run_at.naive_utc()),
+        (None, Some(interval_seconds)) => {
+            Some(after + chrono::Duration::seconds(interval_seconds as i64))
+        }
+        (None, None) => None,
+    }
+}
+
+fn to_timer(chat_timer: &ChatTimer) -> Timer {
+    Timer {
+        timer_id: chat_timer.timer_id,
+        name: chat_timer.name.clone(),
+        message: chat_timer.message.clone(),
+        cron_schedule: chat_timer.cron_schedule.clone().unwrap_or_default(),
+        interval_seconds: chat_timer.interval_seconds.unwrap_or_default(),
+        min_messages: chat_timer.min_messages,
+        run_without_live_broadcast: chat_timer.run_without_live_broadcast,
+        paused: chat_timer.paused,
+        last_run_at_timestamp: chat_timer
+            .last_run_at
+            .map(|last_run_at| to_timestamp(DateTime::from_utc(last_run_at, Utc))),
+        next_run_at_timestamp: Some(to_timestamp(DateTime::from_utc(
+            chat_timer.next_run_at,
+            Utc,
+        ))),
+    }
+}
+
+/// The schedule of a timer as it is stored, empty fields of the request mean no cron schedule or no interval
+fn stored_schedule(timer: &Timer) -> (Option<String>, Option<i32>) {
+    let cron_schedule =
+        Some(timer.cron_schedule.trim().to_string()).filter(|cron| !cron.is_empty());
+    let interval_seconds = Some(timer.interval_seconds).filter(|interval| *interval > 0);
+    (cron_schedule, interval_seconds)
+}
+
+/// The timers of a tenant, kept in the database so they survive a restart
+#[derive(Clone)]
+pub struct TimerStore {
+    database_connection: Pool<ConnectionManager<PgConnection>>,
+    tenant_name: String,
+    /// Wakes up the timer loop when a timer was created, changed or deleted
+    changed: Arc<Notify>,
+}
+
+impl TimerStore {
+    pub fn new(
+        database_connection: &Pool<ConnectionManager<PgConnection>>,
+        tenant_name: &str,
+    ) -> Self {
+        TimerStore {
+            database_connection: database_connection.clone(),
+            tenant_name: tenant_name.to_string(),
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn list(&self) -> Result<Vec<Timer>, Box<dyn std::error::Error>> {
+        use schema::chat_timers::dsl::*;
+        let stored_timers = chat_timers
+            .filter(tenant.eq(&self.tenant_name))
+            .order(name)
+            .load::<ChatTimer>(&self.database_connection.get()?)?;
+        Ok(stored_timers.iter().map(to_timer).collect())
+    }
+
+    /// Checks whether another timer of the tenant already has the name
+    pub fn name_taken(
+        &self,
+        timer_name: &str,
+        except_timer_id: i32,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        use schema::chat_timers::dsl::*;
+        let count: i64 = chat_timers
+            .filter(tenant.eq(&self.tenant_name))
+            .filter(name.eq(timer_name.trim()))
+            .filter(timer_id.ne(except_timer_id))
+            .count()
+            .get_result(&self.database_connection.get()?)?;
+        Ok(count > 0)
+    }
+
+    /// Stores a new timer, it first runs at the next time of its schedule. Returns None if the name is taken.
+    pub fn create(&self, timer: &Timer) -> Result<Option<Timer>, Box<dyn std::error::Error>> {
+        let now = Utc::now().naive_utc();
+        let (cron_schedule, interval_seconds) = stored_schedule(timer);
+        let next_run_at = next_run_after(&cron_schedule, interval_seconds, now);
+        let insert_timer = InsertChatTimer {
+            tenant: self.tenant_name.clone(),
+            name: timer.name.trim().to_string(),
+            message: timer.message.trim().to_string(),
+            cron_schedule,
+            interval_seconds,
+            min_messages: timer.min_messages,
+            run_without_live_broadcast: timer.run_without_live_broadcast,
+            // A schedule without further runs is kept, but never runs
+            paused: timer.paused || next_run_at.is_none(),
+            next_run_at: next_run_at.unwrap_or(now),
+            created_at: now,
+            updated_at: now,
+        };
+        let created_timer: Option<ChatTimer> = diesel::insert_into(schema::chat_timers::table)
+            .values(&insert_timer)
+            .on_conflict_do_nothing()
+            .get_result(&self.database_connection.get()?)
+            .optional()?;
+        self.changed.notify_one();
+        Ok(created_timer.as_ref().map(to_timer))
+    }
+
+    /// Replaces the settings of the timer, its next run is calculated again from now. Returns None if there is no such timer.
+    pub fn update(&self, timer: &Timer) -> Result<Option<Timer>, Box<dyn std::error::Error>> {
+        use schema::chat_timers::dsl::*;
+        let now = Utc::now().naive_utc();
+        let (new_cron_schedule, new_interval_seconds) = stored_schedule(timer);
+        let new_next_run_at = next_run_after(&new_cron_schedule, new_interval_seconds, now);
+        let updated_timer: Option<ChatTimer> = diesel::update(
+            chat_timers
+                .filter(tenant.eq(&self.tenant_name))
+                .filter(timer_id.eq(timer.timer_id)),
+        )
+        .set((
+            name.eq(timer.name.trim()),
+            message.eq(timer.message.trim()),
+            cron_schedule.eq(&new_cron_schedule),
+            interval_seconds.eq(new_interval_seconds),
+            min_messages.eq(timer.min_messages),
+            run_without_live_broadcast.eq(timer.run_without_live_broadcast),
+            paused.eq(timer.paused || new_next_run_at.is_none()),
+            next_run_at.eq(new_next_run_at.unwrap_or(now)),
+            updated_at.eq(now),
+        ))
+        .get_result(&self.database_connection.get()?)
+        .optional()?;
+        self.changed.notify_one();
+        Ok(updated_timer.as_ref().map(to_timer))
+    }
+
+    /// Deletes the timer, returns false if there is no such timer
+    pub fn delete(&self, deleted_timer_id: i32) -> Result<bool, Box<dyn std::error::Error>> {
+        use schema::chat_timers::dsl::*;
+        let deleted = diesel::delete(
+            chat_timers
+                .filter(tenant.eq(&self.tenant_name))
+                .filter(timer_id.eq(deleted_timer_id)),
+        )
+        .execute(&self.database_connection.get()?)?;
+        self.changed.notify_one();
+        Ok(deleted > 0)
+    }
+
+    /// Returns the timers that are not paused and whose next run is due
+    fn due(&self) -> Result<Vec<ChatTimer>, Box<dyn std::error::Error>> {
+        use schema::chat_timers::dsl::*;
+        let due_timers = chat_timers
+            .filter(tenant.eq(&self.tenant_name))
+            .filter(paused.eq(false))
+            .filter(next_run_at.le(Utc::now().naive_utc()))
+            .load::<ChatTimer>(&self.database_connection.get()?)?;
+        Ok(due_timers)
+    }
+
+    /// Returns when the next timer that is not paused runs
+    fn next_run_at(&self) -> Result<Option<NaiveDateTime>, Box<dyn std::error::Error>> {
+        use schema::chat_timers::dsl::*;
+        let next_run: Option<NaiveDateTime> = chat_timers
+            .filter(tenant.eq(&self.tenant_name))
+            .filter(paused.eq(false))
+            .select(diesel::dsl::min(next_run_at))
+            .first(&self.database_connection.get()?)?;
+        Ok(next_run)
+    }
+
+    /// Returns how many chat messages arrived since the time, leaving out the ones this service sent
+    fn messages_since(&self, since: NaiveDateTime) -> Result<i64, Box<dyn std::error::Error>> {
+        use schema::livechat_messages::dsl::*;
+        let count = livechat_messages
+            .filter(tenant.eq(&self.tenant_name))
+            .filter(sent_by_service.eq(false))
+            .filter(received_at.gt(since))
+            .count()
+            .get_result(&self.database_connection.get()?)?;
+        Ok(count)
+    }
+
+    /// Records when the timer ran, if it did, and when it runs next. Timers without a next run are paused.
+    fn record_run(
+        &self,
+        chat_timer: &ChatTimer,
+        ran_at: Option<NaiveDateTime>,
+        new_next_run_at: Option<NaiveDateTime>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        use schema::chat_timers::dsl::*;
+        let connection = self.database_connection.get()?;
+        let timer = chat_timers.filter(timer_id.eq(chat_timer.timer_id));
+        let last_run = last_run_at.eq(ran_at.or(chat_timer.last_run_at));
+        // Only pause a timer that won't run again, it may have been paused through UpdateTimer in the meantime
+        match new_next_run_at {
+            Some(new_next_run_at) => diesel::update(timer)
+                .set((last_run, next_run_at.eq(new_next_run_at)))
+                .execute(&connection)?,
+            None => diesel::update(timer)
+                .set((last_run, paused.eq(true)))
+                .execute(&connection)?,
+        };
+        Ok(())
+    }
+}
+
+/// Queues the message of the timer for every followed chat, unless one of its conditions is not met.
+/// Returns whether the message was queued.
+async fn run_timer(
+    tenant: &Tenant,
+    chat_timer: &ChatTimer,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    if !chat_timer.run_without_live_broadcast {
+        let live = tenant
+            .broadcast_states
+            .read()
+            .await
+            .values()
+            .any(|state| state.status == BroadcastStatus::Live as i32);
+        if !live {
+            debug!("Skipping timer {}, no broadcast is live", chat_timer.name);
+            return Ok(false);
+        }
+    }
+    // Announcements are the first thing to go when the quota runs low
+    if tenant.quota.is_throttled().await {
+        debug!(
+            "Skipping timer {}, the quota threshold is reached",
+            chat_timer.name
+        );
+        return Ok(false);
+    }
+    if chat_timer.min_messages > 0 {
+        let since = chat_timer.last_run_at.unwrap_or(chat_timer.created_at);
+        let message_count = tenant.timers.messages_since(since)?;
+        if message_count < chat_timer.min_messages as i64 {
+            debug!(
+                "Skipping timer {}, only {} of {} messages arrived since it last ran",
+                chat_timer.name, message_count, chat_timer.min_messages
+            );
+            return Ok(false);
+        }
+    }
+    let livechat_ids = match tenant.target_livechat_ids("").await {
+        Ok(livechat_ids) => livechat_ids,
+        Err(_) => {
+            debug!(
+                "Skipping timer {}, no livechat is followed",
+                chat_timer.name
+            );
+            return Ok(false);
+        }
+    };
+    // The key stays the same for a run, so the message is not queued twice if recording the run fails
+    let idempotency_key = format!(
+        "timer-{}-{}",
+        chat_timer.timer_id,
+        chat_timer.next_run_at.timestamp()
+    );
+    tenant.send_queue.enqueue(
+        &idempotency_key,
+        &livechat_ids,
+        &[chat_timer.message.clone()],
+        MessagePriority::PriorityLow,
+        AccountId::BOT,
+    )?;
+    info!(
+        "Timer {} queued its message for {} livechats",
+        chat_timer.name,
+        livechat_ids.len()
+    );
+    Ok(true)
+}
+
+/// Runs the timers of the tenant when they are due. Runs missed while the service was down are skipped.
+pub async fn run_timers(tenant: &Tenant) {
+    let timers = &tenant.timers;
+    loop {
+        let due_timers = match timers.due() {
+            Ok(due_timers) => due_timers,
+            Err(e) => {
+                error!("Unable to read the timers: {}", e);
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                continue;
+            }
+        };
+        for chat_timer in &due_timers {
+            let ran = match run_timer(tenant, chat_timer).await {
+                Ok(ran) => ran,
+                Err(e) => {
+                    error!("Unable to run timer {}: {}", chat_timer.name, e);
+                    false
+                }
+            };
+            let now = Utc::now().naive_utc();
+            let next_run_at =
+                next_run_after(&chat_timer.cron_schedule, chat_timer.interval_seconds, now);
+            if next_run_at.is_none() {
+                info!("Timer {} has no further runs, pausing it", chat_timer.name);
+            }
+            if let Err(e) =
+                timers.record_run(chat_timer, if ran { Some(now) } else { None }, next_run_at)
+            {
+                error!(
+                    "Unable to record the run of timer {}: {}",
+                    chat_timer.name, e
+                );
+            }
+        }
+        // Wait for the next timer or a change of the timers, whatever comes first
+        let wait_for = match timers.next_run_at() {
+            Ok(Some(next_run_at)) => (next_run_at - Utc::now().naive_utc())
+                .to_std()
+                .unwrap_or_default(),
+            Ok(None) => Duration::from_secs(3600),
+            Err(e) => {
+                error!("Unable to read the timers: {}", e);
+                Duration::from_secs(10)
+            }
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(wait_for) => {}
+            _ = timers.changed.notified() => {}
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use futures_util::future::join_all;
+use google_youtube3::YouTube;
+use log::{error, info};
+use tokio::sync::RwLock;
+
+use crate::auth::{to_timestamp, AccountId, AuthChallenges};
+use crate::tenant::Tenant;
+use crate::youtube::SCOPES;
+use crate::youtube_service::{AccountTokenStatus, AuthAccount, AuthState, TokenHealth};
+
+/// The last checked token state of the accounts of a tenant, keyed by account
+pub type TokenStatuses = Arc<RwLock<HashMap<AccountId, AccountTokenStatus>>>;
+
+/// Asks the authenticator of the account for a token, which refreshes it if it is about to expire.
+/// If the refresh token was revoked, yup-oauth2 falls back to the device flow and this only returns once the account is authenticated again.
+async fn check_token(
+    tenant: &Tenant,
+    account: AccountId,
+    hub: &YouTube,
+    challenges: &AuthChallenges,
+) {
+    let token_result = hub.auth.token(&SCOPES).await;
+    let checked_at = to_timestamp(Utc::now());
+
+    let mut statuses = tenant.token_statuses.write().await;
+    let status = statuses
+        .entry(account)
+        .or_insert_with(|| AccountTokenStatus {
+            account: account.account as i32,
+            reader: account.reader,
+            ..Default::default()
+        });
+    let previous_health = status.health;
+    status.last_check_timestamp = Some(checked_at.clone());
+    match token_result {
+        Ok(token) => {
+            // A different expiry means the access token was refreshed since the last check
+            let expires_at = token.expiration_time().map(to_timestamp);
+            if expires_at != status.expires_at_timestamp {
+                status.expires_at_timestamp = expires_at;
+                status.last_refresh_timestamp = Some(checked_at);
+            }
+            status.health = TokenHealth::TokenValid as i32;
+            status.error = String::new();
+        }
+        Err(e) => {
+            error!(
+                "The {} token of tenant {} stopped working: {}",
+                account, tenant.name, e
+            );
+            status.health = TokenHealth::TokenReauthRequired as i32;
+            status.error = e.to_string();
+        }
+    }
+    let health = status.health;
+    drop(statuses);
+
+    // Let the operators know when an account has to be authenticated again and when it is back
+    if health != previous_health {
+        if health == TokenHealth::TokenReauthRequired as i32 {
+            challenges
+                .finish(&tenant.name, account, AuthState::ReauthRequired)
+                .await;
+        } else if previous_health == TokenHealth::TokenReauthRequired as i32 {
+            info!(
+                "The {} token of tenant {} works again",
+                account, tenant.name
+            );
+            challenges
+                .finish(&tenant.name, account, AuthState::Authenticated)
+                .await;
+        }
+    }
+}
+
+/// Periodically checks the tokens of the bot, the streamer and the reader accounts of the tenant
+pub async fn watch_tokens(tenant: &Tenant, challenges: &AuthChallenges) {
+    let interval_secs: u64 = env::var("YTS_TOKEN_CHECK_INTERVAL")
+        .ok()
+        .and_then(|interval| interval.parse().ok())
+        .unwrap_or(300);
+    loop {
+        let reader_checks = tenant
+            .read_hubs
+            .iter()
+            .filter(|(account, _)| account.account == AuthAccount::Reader)
+            .map(|(account, hub)| check_token(tenant, *account, hub, challenges));
+        tokio::join!(
+            check_token(tenant, AccountId::BOT, &tenant.bot_hub, challenges),
+            check_token(
+                tenant,
+                AccountId::STREAMER,
+                &tenant.streamer_hub,
+                challenges
+            ),
+            join_all(reader_checks)
+        );
+        tokio::time::sleep(Duration::from_secs(interval_secs)).await;
+    }
+}
@@ -0,0 +1,235 @@
+use std::env;
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use log::{debug, error, info};
+use r2d2::Pool;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use yup_oauth2::storage::TokenInfo;
+
+use crate::auth::AccountId;
+use crate::models::InsertOauthToken;
+use crate::schema;
+
+/// Where the tokens of the authenticated accounts are kept between restarts.
+/// With Postgres, yup-oauth2 reads and writes them in the database instead of its token cache files,
+/// so containers don't need a volume to stay authenticated.
+#[derive(Clone)]
+pub enum TokenStorage {
+    File,
+    Postgres {
+        database_connection: Pool<ConnectionManager<PgConnection>>,
+        key: [u8; 32],
+    },
+}
+
+/// Reads the token storage from the environment.
+/// YTS_TOKEN_STORAGE is either "file", the default, or "postgres".
+/// "postgres" needs YTS_TOKEN_ENCRYPTION_KEY, 32 random bytes in base64, e.g. from `openssl rand -base64 32`.
+pub fn token_storage_from_env(
+    database_connection: &Pool<ConnectionManager<PgConnection>>,
+) -> TokenStorage {
+    match env::var("YTS_TOKEN_STORAGE").as_deref() {
+        Ok("postgres") => {
+            let encoded_key = env::var("YTS_TOKEN_ENCRYPTION_KEY")
+                .expect("YTS_TOKEN_ENCRYPTION_KEY must be set to store tokens in Postgres");
+            let key_bytes = base64::decode(encoded_key.trim())
+                .expect("YTS_TOKEN_ENCRYPTION_KEY must be base64");
+            if key_bytes.len() != 32 {
+                panic!("YTS_TOKEN_ENCRYPTION_KEY must be 32 bytes long");
+            }
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&key_bytes);
+            info!("Storing tokens in Postgres");
+            TokenStorage::Postgres {
+                database_connection: database_connection.clone(),
+                key,
+            }
+        }
+        Ok("file") | Ok("") | Err(_) => {
+            info!("Storing tokens in files");
+            TokenStorage::File
+        }
+        Ok(other) => panic!("Unknown token storage {}", other),
+    }
+}
+
+/// Binds the encrypted token to its account, so it can't be swapped with the token of another account
+fn associated_data(tenant: &str, account: AccountId) -> String {
+    format!("{}/{}", tenant, account)
+}
+
+fn encrypt(
+    key: &[u8; 32],
+    associated_data: &str,
+    token: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), Box<dyn std::error::Error>> {
+    let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).map_err(|_| "Invalid key")?);
+    // Every encryption gets a fresh random nonce, which is stored next to the token
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| "Unable to generate a nonce")?;
+    let mut encrypted_token = token.to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(associated_data.as_bytes()),
+        &mut encrypted_token,
+    )
+    .map_err(|_| "Unable to encrypt the token")?;
+    Ok((nonce.to_vec(), encrypted_token))
+}
+
+fn decrypt(
+    key: &[u8; 32],
+    associated_data: &str,
+    nonce: &[u8],
+    mut encrypted_token: Vec<u8>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).map_err(|_| "Invalid key")?);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| "Invalid nonce")?;
+    let token = key
+        .open_in_place(
+            nonce,
+            Aad::from(associated_data.as_bytes()),
+            &mut encrypted_token,
+        )
+        .map_err(|_| "Unable to decrypt the token, was YTS_TOKEN_ENCRYPTION_KEY changed?")?;
+    Ok(token.to_vec())
+}
+
+impl TokenStorage {
+    /// Returns where the authenticator of the account keeps its tokens, None if yup-oauth2 keeps them in its token cache file
+    pub fn account_storage(
+        &self,
+        tenant_name: &str,
+        account: AccountId,
+    ) -> Option<AccountTokenStorage> {
+        match self {
+            TokenStorage::File => None,
+            TokenStorage::Postgres {
+                database_connection,
+                key,
+            } => Some(AccountTokenStorage {
+                database_connection: database_connection.clone(),
+                key: *key,
+                tenant_name: tenant_name.to_string(),
+                account,
+            }),
+        }
+    }
+}
+
+/// A token and the scopes it was issued for, the same layout yup-oauth2 uses in its token cache files
+#[derive(Serialize, Deserialize)]
+struct StoredToken {
+    scopes: Vec<String>,
+    token: TokenInfo,
+}
+
+/// Keeps the tokens of one account encrypted in the database, yup-oauth2 reads and writes them through it
+pub struct AccountTokenStorage {
+    database_connection: Pool<ConnectionManager<PgConnection>>,
+    key: [u8; 32],
+    tenant_name: String,
+    account: AccountId,
+}
+
+impl AccountTokenStorage {
+    fn load(&self) -> Result<Vec<StoredToken>, Box<dyn std::error::Error>> {
+        use schema::oauth_tokens::dsl;
+        let stored_token: Option<(Vec<u8>, Vec<u8>)> = dsl::oauth_tokens
+            .filter(dsl::tenant.eq(&self.tenant_name))
+            .filter(dsl::account.eq(self.account.to_string()))
+            .select((dsl::nonce, dsl::encrypted_token))
+            .first(&self.database_connection.get()?)
+            .optional()?;
+        let (nonce, encrypted_token) = match stored_token {
+            Some(stored_token) => stored_token,
+            None => return Ok(Vec::new()),
+        };
+        let tokens = decrypt(
+            &self.key,
+            &associated_data(&self.tenant_name, self.account),
+            &nonce,
+            encrypted_token,
+        )?;
+        Ok(serde_json::from_slice(&tokens)?)
+    }
+
+    fn store(&self, tokens: &[StoredToken]) -> Result<(), Box<dyn std::error::Error>> {
+        let (nonce, encrypted_token) = encrypt(
+            &self.key,
+            &associated_data(&self.tenant_name, self.account),
+            &serde_json::to_vec(tokens)?,
+        )?;
+        let insert_token = InsertOauthToken {
+            tenant: self.tenant_name.clone(),
+            account: self.account.to_string(),
+            nonce,
+            encrypted_token,
+            updated_at: chrono::Utc::now().naive_utc(),
+        };
+        use schema::oauth_tokens::dsl;
+        diesel::insert_into(dsl::oauth_tokens)
+            .values(&insert_token)
+            .on_conflict((dsl::tenant, dsl::account))
+            .do_update()
+            .set(&insert_token)
+            .execute(&self.database_connection.get()?)?;
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl yup_oauth2::storage::TokenStorage for AccountTokenStorage {
+    async fn set(&self, scopes: &[&str], token: TokenInfo) -> anyhow::Result<()> {
+        let mut tokens = match self.load() {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                // Unreadable tokens are replaced, the account has to be authenticated again anyway
+                error!(
+                    "Unable to read the stored tokens of the {} account of tenant {}: {}",
+                    self.account, self.tenant_name, e
+                );
+                Vec::new()
+            }
+        };
+        let mut scopes: Vec<String> = scopes.iter().map(|scope| scope.to_string()).collect();
+        scopes.sort();
+        tokens.retain(|stored_token| stored_token.scopes != scopes);
+        tokens.push(StoredToken { scopes, token });
+        self.store(&tokens)
+            .map_err(|e| anyhow::anyhow!("Unable to store the tokens: {}", e))?;
+        debug!(
+            "Stored the tokens of the {} account of tenant {}",
+            self.account, self.tenant_name
+        );
+        Ok(())
+    }
+
+    async fn get(&self, scopes: &[&str]) -> Option<TokenInfo> {
+        let tokens = match self.load() {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                error!(
+                    "Unable to read the stored tokens of the {} account of tenant {}: {}",
+                    self.account, self.tenant_name, e
+                );
+                return None;
+            }
+        };
+        // A token issued for more scopes than requested works as well
+        tokens
+            .into_iter()
+            .find(|stored_token| {
+                scopes
+                    .iter()
+                    .all(|scope| stored_token.scopes.iter().any(|stored| stored == scope))
+            })
+            .map(|stored_token| stored_token.token)
+    }
+}
@@ -1,8 +1,18 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use google_youtube3::api::LiveBroadcast;
 use google_youtube3::YouTube;
-use hyper::{Body, Response};
+use hyper::{Body, Request, Response};
 use log::{error, info};
+use serde::Deserialize;
 use yup_oauth2::DeviceFlowAuthenticator;
 
+use crate::auth::{AccountId, AuthChallenges, ChallengeDelegate};
+use crate::tenant::{tenant_env_var, Tenant, DEFAULT_TENANT};
+use crate::token_storage::TokenStorage;
+use crate::youtube_service::AuthState;
+
 /// Because hyper stores the body weirdly, we need to first convert it to bytes (which works asynchronously) and then decode those bytes to UTF-8.
 /// Thanks hyper.
 pub async fn body_to_string(mut response: Response<Body>) -> String {
@@ -12,79 +22,281 @@ pub async fn body_to_string(mut response: Response<Body>) -> String {
     String::from_utf8(body_bytes.to_vec()).unwrap()
 }
 
-/// Creates an authenticator that works with the device flow and immediately requests a token for the youtube scope.
-/// Please be on the lookout for a message in the log for authenticating.
-pub async fn authenticate_google() -> Result<(YouTube, YouTube), Box<dyn std::error::Error>> {
-    let bot_secret = yup_oauth2::read_application_secret("clientsecret.json")
-        .await
-        .expect("clientsecret.json");
-    let streamer_secret = yup_oauth2::read_application_secret("clientsecret.json")
-        .await
-        .expect("clientsecret.json");
+/// The scopes every token is requested for
+pub const SCOPES: [&str; 2] = [
+    "https://www.googleapis.com/auth/youtube",
+    "https://www.googleapis.com/auth/youtube.readonly",
+];
 
-    let bot_auth = DeviceFlowAuthenticator::builder(bot_secret)
-        .persist_tokens_to_disk("tokencache_bot.json")
-        .build()
-        .await
-        .unwrap();
-    let streamer_auth = DeviceFlowAuthenticator::builder(streamer_secret)
-        .persist_tokens_to_disk("tokencache_streamer.json")
-        .build()
+/// Creates an authenticator for one account that works with the device flow and keeps asking for a token for the youtube scope until it gets one.
+/// The challenges are logged and handed to `AuthChallenges`, so the account can be authenticated through the gRPC API as well.
+async fn authenticate_account(
+    tenant: &str,
+    account: AccountId,
+    client_secret: &str,
+    token_cache: String,
+    challenges: &AuthChallenges,
+    token_storage: &TokenStorage,
+) -> Result<YouTube, Box<dyn std::error::Error>> {
+    let secret = yup_oauth2::read_application_secret(client_secret)
         .await
-        .unwrap();
-    info!("---------- BOT AUTHENTICATION ----------");
-    let _ = bot_auth
-        .token(&[
-            "https://www.googleapis.com/auth/youtube",
-            "https://www.googleapis.com/auth/youtube.readonly",
-        ])
-        .await;
-    info!("---------- END BOT AUTHENTICATION ----------");
-    info!("---------- STREAMER AUTHENTICATION ----------");
-    let _ = streamer_auth
-        .token(&[
-            "https://www.googleapis.com/auth/youtube",
-            "https://www.googleapis.com/auth/youtube.readonly",
-        ])
+        .expect(client_secret);
+    let builder = DeviceFlowAuthenticator::builder(secret).flow_delegate(Box::new(
+        ChallengeDelegate::new(tenant, account, challenges.clone()),
+    ));
+    let auth = match token_storage.account_storage(tenant, account) {
+        Some(account_storage) => builder.with_storage(Box::new(account_storage)),
+        None => builder.persist_tokens_to_disk(token_cache),
+    }
+    .build()
+    .await?;
+    info!(
+        "---------- {} AUTHENTICATION ({}) ----------",
+        account, tenant
+    );
+    loop {
+        match auth.token(&SCOPES).await {
+            Ok(_) => break,
+            Err(e) => {
+                // Most likely nobody entered the code in time, the next attempt presents a new one
+                error!(
+                    "Authentication of the {} account of tenant {} failed: {}",
+                    account, tenant, e
+                );
+                challenges
+                    .finish(tenant, account, AuthState::AuthFailed)
+                    .await;
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+        }
+    }
+    challenges
+        .finish(tenant, account, AuthState::Authenticated)
         .await;
-    info!("---------- END STREAMER AUTHENTICATION ----------");
-    let bot_hub = YouTube::new(
-        hyper::Client::builder().build(hyper_rustls::HttpsConnector::with_native_roots()),
-        bot_auth,
+    info!(
+        "---------- END {} AUTHENTICATION ({}) ----------",
+        account, tenant
     );
-    let streamer_hub = YouTube::new(
+    Ok(YouTube::new(
         hyper::Client::builder().build(hyper_rustls::HttpsConnector::with_native_roots()),
-        streamer_auth,
-    );
+        auth,
+    ))
+}
+
+/// Authenticates the bot and the streamer account of the tenant, one after the other.
+/// Every tenant has its own bot and streamer token cache, only the default tenant keeps the original file names.
+/// The tokens are kept in these files or the database, depending on the token storage.
+/// Please be on the lookout for a message in the log or a challenge from SubscribeAuthChallenges for authenticating.
+pub async fn authenticate_google(
+    tenant: &str,
+    challenges: &AuthChallenges,
+    token_storage: &TokenStorage,
+) -> Result<(YouTube, YouTube), Box<dyn std::error::Error>> {
+    let (bot_token_cache, streamer_token_cache) = if tenant == DEFAULT_TENANT {
+        (
+            "tokencache_bot.json".to_string(),
+            "tokencache_streamer.json".to_string(),
+        )
+    } else {
+        (
+            format!("tokencache_{}_bot.json", tenant),
+            format!("tokencache_{}_streamer.json", tenant),
+        )
+    };
+    let bot_hub = authenticate_account(
+        tenant,
+        AccountId::BOT,
+        "clientsecret.json",
+        bot_token_cache,
+        challenges,
+        token_storage,
+    )
+    .await?;
+    let streamer_hub = authenticate_account(
+        tenant,
+        AccountId::STREAMER,
+        "clientsecret.json",
+        streamer_token_cache,
+        challenges,
+        token_storage,
+    )
+    .await?;
     Ok((bot_hub, streamer_hub))
 }
 
-/// Get the livechat id for the currently signed in user of the hub.
-pub async fn get_livechat_id(hub: &YouTube) -> Option<String> {
-    let broadcasts_response = hub
+/// Authenticates the readers of the tenant, one account for every client secret in YTS_READ_CLIENT_SECRETS, a comma separated list of files.
+/// Every client secret should belong to its own Google project, so every reader brings its own daily quota.
+/// Readers only poll chats, everything else stays with the bot and the streamer.
+pub async fn authenticate_readers(
+    tenant: &str,
+    challenges: &AuthChallenges,
+    token_storage: &TokenStorage,
+) -> Result<Vec<(AccountId, YouTube)>, Box<dyn std::error::Error>> {
+    let client_secrets: Vec<String> = tenant_env_var(tenant, "READ_CLIENT_SECRETS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|client_secret| client_secret.trim().to_string())
+        .filter(|client_secret| !client_secret.is_empty())
+        .collect();
+    let mut readers = Vec::new();
+    for (index, client_secret) in client_secrets.iter().enumerate() {
+        let account = AccountId::reader(index as u32 + 1);
+        let token_cache = if tenant == DEFAULT_TENANT {
+            format!("tokencache_reader{}.json", account.reader)
+        } else {
+            format!("tokencache_{}_reader{}.json", tenant, account.reader)
+        };
+        let hub = authenticate_account(
+            tenant,
+            account,
+            client_secret,
+            token_cache,
+            challenges,
+            token_storage,
+        )
+        .await?;
+        readers.push((account, hub));
+    }
+    Ok(readers)
+}
+
+/// Lists the broadcasts of the streamer of the tenant with the given status, e.g. "active" or "upcoming",
+/// and type, which is either "all", "event" or "persistent".
+pub async fn list_broadcasts(
+    tenant: &Tenant,
+    broadcast_status: &str,
+    broadcast_type: &str,
+) -> Option<Vec<LiveBroadcast>> {
+    tenant
+        .record_call(AccountId::STREAMER, "liveBroadcasts.list")
+        .await;
+    let broadcasts_response = tenant
+        .streamer_hub
         .live_broadcasts()
-        .list(&vec!["".to_string()])
-        .broadcast_status("active")
-        .broadcast_type("all")
+        .list(&vec!["snippet".to_string(), "status".to_string()])
+        .broadcast_status(broadcast_status)
+        .broadcast_type(broadcast_type)
+        .max_results(50)
         .doit()
         .await;
     if let Err(e) = broadcasts_response {
-        error!("Unable to fetch livechat id: {}", e);
+        error!("Unable to fetch {} broadcasts: {}", broadcast_status, e);
+        return None;
+    }
+    let (_, response) = broadcasts_response.expect("msg");
+    Some(response.items.unwrap_or_default())
+}
+
+/// Gets the broadcasts with the given ids, regardless of their status.
+pub async fn get_broadcasts_by_id(tenant: &Tenant, ids: &[String]) -> Option<Vec<LiveBroadcast>> {
+    tenant
+        .record_call(AccountId::STREAMER, "liveBroadcasts.list")
+        .await;
+    let mut prepare_broadcasts = tenant
+        .streamer_hub
+        .live_broadcasts()
+        .list(&vec!["snippet".to_string(), "status".to_string()]);
+    for id in ids {
+        prepare_broadcasts = prepare_broadcasts.add_id(id.as_str());
+    }
+    let broadcasts_response = prepare_broadcasts.doit().await;
+    if let Err(e) = broadcasts_response {
+        error!("Unable to fetch broadcasts by id: {}", e);
         return None;
     }
     let (_, response) = broadcasts_response.expect("msg");
-    let items = response.items;
+    Some(response.items.unwrap_or_default())
+}
+
+/// The details of a membership gifting event
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MembershipGiftingDetails {
+    pub gift_memberships_count: Option<i32>,
+    pub gift_memberships_level_name: Option<String>,
+}
+
+/// The details of a membership a user received as a gift
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GiftMembershipReceivedDetails {
+    pub member_level_name: Option<String>,
+    pub gifter_channel_id: Option<String>,
+    pub associated_membership_gifting_message_id: Option<String>,
+}
 
-    match items {
-        Some(broadcasts) => {
-            if broadcasts.is_empty() {
-                return None;
+/// The gifting details of a chat message, only one of them is set
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GiftingSnippet {
+    pub membership_gifting_details: Option<MembershipGiftingDetails>,
+    pub gift_membership_received_details: Option<GiftMembershipReceivedDetails>,
+}
+
+#[derive(Deserialize)]
+struct GiftingMessage {
+    id: String,
+    #[serde(default)]
+    snippet: GiftingSnippet,
+}
+
+#[derive(Deserialize)]
+struct GiftingPage {
+    #[serde(default)]
+    items: Vec<GiftingMessage>,
+}
+
+/// Encodes a query parameter, only unreserved characters are kept as they are
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
             }
-            let first_broadcast = broadcasts.get(0).unwrap();
-            let snippet_option = &first_broadcast.snippet;
-            let snippet = snippet_option.as_ref().unwrap();
-            snippet.live_chat_id.clone()
-        }
-        None => None,
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Fetches the gifting details of the messages on a page of the livechat, keyed by the message id.
+/// google-youtube3 predates membership gifting and drops these fields, so the page is requested again
+/// with only the gifting fields and read without the generated types.
+pub async fn fetch_gifting_details(
+    hub: &YouTube,
+    livechat_id: &str,
+    page_token: Option<&str>,
+) -> Result<HashMap<String, GiftingSnippet>, Box<dyn std::error::Error>> {
+    let token = hub.auth.token(&SCOPES).await?;
+    let mut url = format!(
+        "https://youtube.googleapis.com/youtube/v3/liveChat/messages?part=snippet&liveChatId={}&fields={}",
+        encode_query_value(livechat_id),
+        encode_query_value("items(id,snippet(membershipGiftingDetails,giftMembershipReceivedDetails))")
+    );
+    if let Some(page_token) = page_token {
+        url.push_str("&pageToken=");
+        url.push_str(&encode_query_value(page_token));
+    }
+    let request = Request::get(url)
+        .header(
+            hyper::header::AUTHORIZATION,
+            format!("Bearer {}", token.as_str()),
+        )
+        .body(Body::empty())?;
+    let response = hub.client.request(request).await?;
+    let status = response.status();
+    let body_string = body_to_string(response).await;
+    if !status.is_success() {
+        return Err(format!(
+            "Fetching gifting details failed with {}: {}",
+            status, body_string
+        )
+        .into());
     }
+    let page: GiftingPage = serde_json::from_str(&body_string)?;
+    Ok(page
+        .items
+        .into_iter()
+        .map(|message| (message.id, message.snippet))
+        .collect())
 }